@@ -0,0 +1,120 @@
+use std::fmt;
+
+// Location of a token in the sources, columns count characters from 1
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    // Index in `Sources`
+    pub file: usize,
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+}
+
+impl Span {
+    // From the start of `self` to the end of `other`, or just `self` unless `other`
+    // follows it on the same line, e.g. for a macro argument inside the macro body
+    pub fn to(self, other: Span) -> Span {
+        if (other.file, other.line) != (self.file, self.line) || other.column < self.column {
+            return self;
+        }
+        Span {
+            length: other.column + other.length - self.column,
+            ..self
+        }
+    }
+}
+
+// An assembler error, pointing at the offending part of the source
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(message: String, span: Span) -> Self {
+        Self {
+            message,
+            span,
+            help: None,
+        }
+    }
+
+    pub fn with_help(mut self, help: String) -> Self {
+        self.help = Some(help);
+        self
+    }
+
+    // error: Invalid register `V16`
+    //  --> game.chasm:7:4
+    //   |
+    // 7 | LD V16, 1
+    //   |    ^^^
+    //   = help: `V16` is not a register, did you mean `VF`?
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let line_number = self.span.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+        let source_line = source.lines().nth(self.span.line - 1).unwrap_or("");
+        // Keep the tabs of the source line so the carets line up
+        let indent: String = source_line
+            .chars()
+            .take(self.span.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        let mut text = format!(
+            "error: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}",
+            self.message,
+            gutter,
+            file_name,
+            self.span.line,
+            self.span.column,
+            gutter,
+            line_number,
+            source_line,
+            gutter,
+            indent,
+            "^".repeat(self.span.length.max(1))
+        );
+        if let Some(help) = &self.help {
+            text.push_str(&format!("\n{} = help: {}", gutter, help));
+        }
+        text
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[Line {}:{}] {}",
+            self.span.line, self.span.column, self.message
+        )
+    }
+}
+
+// The candidate closest to a misspelled word, if any is close enough
+pub fn closest<'a>(word: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let word = word.to_uppercase();
+    candidates
+        .map(|candidate| (edit_distance(&word, &candidate.to_uppercase()), candidate))
+        .filter(|(distance, _)| *distance <= word.len().div_ceil(3))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+// Levenshtein distance
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + (ca != *cb) as usize;
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use super::diagnostic::{closest, Diagnostic, Span};
+use super::token::{Token, TokenType};
+
+// What a name stands for. Constants are evaluated when used,
+// so they may refer to labels defined later.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Symbol {
+    // Up to 0x10000 for a label after the last byte of memory
+    Label(usize),
+    Constant(Expr),
+}
+
+pub type Symbols = HashMap<String, Symbol>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+}
+
+impl BinaryOp {
+    fn from_token(token_type: &TokenType) -> Option<Self> {
+        let op = match token_type {
+            TokenType::Plus => BinaryOp::Add,
+            TokenType::Minus => BinaryOp::Sub,
+            TokenType::Star => BinaryOp::Mul,
+            TokenType::Slash => BinaryOp::Div,
+            TokenType::Percent => BinaryOp::Rem,
+            TokenType::Ampersand => BinaryOp::And,
+            TokenType::Pipe => BinaryOp::Or,
+            TokenType::Caret => BinaryOp::Xor,
+            TokenType::Shl => BinaryOp::Shl,
+            TokenType::Shr => BinaryOp::Shr,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    // C precedence, higher binds tighter
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 5,
+            BinaryOp::Add | BinaryOp::Sub => 4,
+            BinaryOp::Shl | BinaryOp::Shr => 3,
+            BinaryOp::And => 2,
+            BinaryOp::Xor => 1,
+            BinaryOp::Or => 0,
+        }
+    }
+}
+
+// hi(x) and lo(x), the high and low bytes of a 16-bit value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    Hi,
+    Lo,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Name(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(Function, Box<Expr>),
+}
+
+impl Expr {
+    // Errors point at `span`, the whole operand
+    pub fn eval(&self, symbols: &Symbols, span: Span) -> Result<i64, Diagnostic> {
+        self.eval_with(symbols, span, &mut Vec::new())
+    }
+
+    // `constants` holds the constants being evaluated, to detect cycles
+    fn eval_with<'a>(
+        &'a self,
+        symbols: &'a Symbols,
+        span: Span,
+        constants: &mut Vec<&'a str>,
+    ) -> Result<i64, Diagnostic> {
+        let overflow = || Diagnostic::new(String::from("Overflow in expression"), span);
+        let value = match self {
+            Expr::Number(n) => *n,
+            Expr::Name(name) => match symbols.get(name) {
+                Some(Symbol::Label(address)) => *address as i64,
+                Some(Symbol::Constant(expr)) => {
+                    if constants.contains(&name.as_str()) {
+                        return Err(Diagnostic::new(
+                            format!("Constant `{}` is defined in terms of itself", name),
+                            span,
+                        )
+                        .with_help(format!(
+                            "{} -> {}",
+                            constants.join(" -> "),
+                            name
+                        )));
+                    }
+                    constants.push(name);
+                    let value = expr.eval_with(symbols, span, constants)?;
+                    constants.pop();
+                    value
+                }
+                None => {
+                    let diagnostic = Diagnostic::new(format!("Undefined name `{}`", name), span);
+                    return Err(match closest(name, symbols.keys().map(String::as_str)) {
+                        Some(known) => diagnostic.with_help(format!("did you mean `{}`?", known)),
+                        None => diagnostic,
+                    });
+                }
+            },
+            Expr::Unary(op, operand) => {
+                let operand = operand.eval_with(symbols, span, constants)?;
+                match op {
+                    UnaryOp::Negate => operand.checked_neg().ok_or_else(overflow)?,
+                    UnaryOp::Not => !operand,
+                }
+            }
+            Expr::Binary(op, left, right) => {
+                let left = left.eval_with(symbols, span, constants)?;
+                let right = right.eval_with(symbols, span, constants)?;
+                let shift = || {
+                    u32::try_from(right)
+                        .ok()
+                        .filter(|bits| *bits < 64)
+                        .ok_or_else(|| Diagnostic::new(format!("Invalid shift by {}", right), span))
+                };
+                if right == 0 && matches!(op, BinaryOp::Div | BinaryOp::Rem) {
+                    return Err(Diagnostic::new(String::from("Division by zero"), span));
+                }
+                match op {
+                    BinaryOp::Add => left.checked_add(right).ok_or_else(overflow)?,
+                    BinaryOp::Sub => left.checked_sub(right).ok_or_else(overflow)?,
+                    BinaryOp::Mul => left.checked_mul(right).ok_or_else(overflow)?,
+                    BinaryOp::Div => left.checked_div(right).ok_or_else(overflow)?,
+                    BinaryOp::Rem => left.checked_rem(right).ok_or_else(overflow)?,
+                    BinaryOp::And => left & right,
+                    BinaryOp::Or => left | right,
+                    BinaryOp::Xor => left ^ right,
+                    BinaryOp::Shl => left.checked_shl(shift()?).ok_or_else(overflow)?,
+                    BinaryOp::Shr => left >> shift()?,
+                }
+            }
+            Expr::Call(function, argument) => {
+                let argument = argument.eval_with(symbols, span, constants)?;
+                match function {
+                    Function::Hi => (argument >> 8) & 0xFF,
+                    Function::Lo => argument & 0xFF,
+                }
+            }
+        };
+        Ok(value)
+    }
+}
+
+// Precedence climbing over the tokens of one operand, which must all be used
+pub fn parse(tokens: &[Token]) -> Result<Expr, Diagnostic> {
+    let mut parser = ExprParser { tokens, current: 0 };
+    let expr = parser.expression(0)?;
+    match tokens.get(parser.current) {
+        None => Ok(expr),
+        Some(token) => Err(Diagnostic::new(
+            format!("Unexpected `{}` in expression", token.token_type),
+            token.span,
+        )
+        .with_help(String::from(
+            "operands are separated by commas, and operators are + - * / % & | ^ ~ << >>",
+        ))),
+    }
+}
+
+struct ExprParser<'a> {
+    tokens: &'a [Token],
+    current: usize,
+}
+
+impl ExprParser<'_> {
+    fn peek(&self) -> Option<&TokenType> {
+        self.tokens.get(self.current).map(|token| &token.token_type)
+    }
+
+    // Where an error about a missing token points
+    fn span(&self) -> Span {
+        match self.tokens.get(self.current).or_else(|| self.tokens.last()) {
+            Some(token) => token.span,
+            None => Span::default(),
+        }
+    }
+
+    fn expression(&mut self, min_precedence: u8) -> Result<Expr, Diagnostic> {
+        let mut left = self.unary()?;
+        while let Some(op) = self.peek().and_then(BinaryOp::from_token) {
+            if op.precedence() < min_precedence {
+                break;
+            }
+            self.current += 1;
+            let right = self.expression(op.precedence() + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, Diagnostic> {
+        let op = match self.peek() {
+            Some(TokenType::Minus) => UnaryOp::Negate,
+            Some(TokenType::Tilde) => UnaryOp::Not,
+            Some(TokenType::Plus) => {
+                self.current += 1;
+                return self.unary();
+            }
+            _ => return self.primary(),
+        };
+        self.current += 1;
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expr, Diagnostic> {
+        let token = match self.tokens.get(self.current) {
+            Some(token) => token,
+            None => {
+                return Err(Diagnostic::new(
+                    String::from("Expected a value"),
+                    self.span(),
+                ))
+            }
+        };
+        self.current += 1;
+        match &token.token_type {
+            TokenType::Number(n) => Ok(Expr::Number(*n as i64)),
+            TokenType::Label(name) if self.peek() == Some(&TokenType::LeftParen) => {
+                let function = match name.to_lowercase().as_str() {
+                    "hi" => Function::Hi,
+                    "lo" => Function::Lo,
+                    _ => {
+                        return Err(Diagnostic::new(
+                            format!("Unknown function `{}`", name),
+                            token.span,
+                        )
+                        .with_help(String::from("the functions are `hi()` and `lo()`")))
+                    }
+                };
+                self.current += 1;
+                let argument = self.expression(0)?;
+                self.close_paren()?;
+                Ok(Expr::Call(function, Box::new(argument)))
+            }
+            TokenType::Label(name) => Ok(Expr::Name(name.clone())),
+            TokenType::LeftParen => {
+                let expr = self.expression(0)?;
+                self.close_paren()?;
+                Ok(expr)
+            }
+            other => Err(Diagnostic::new(
+                format!("Expected a value, found `{}`", other),
+                token.span,
+            )),
+        }
+    }
+
+    fn close_paren(&mut self) -> Result<(), Diagnostic> {
+        if self.peek() == Some(&TokenType::RightParen) {
+            self.current += 1;
+            Ok(())
+        } else {
+            Err(Diagnostic::new(String::from("Expected `)`"), self.span()))
+        }
+    }
+}
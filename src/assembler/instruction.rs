@@ -1,14 +1,212 @@
-// Instructions will consist of:
-//  opcode
-//  Option<LabelAssign>
-//  Option<Register1> 
-//  Option<Register2> 
-//  Option<Immediate>
-//  Option<Label>
-pub struct Instruction;
+use super::diagnostic::{Diagnostic, Span};
+use super::expression::{Expr, Symbols};
 
+// Width of the opcode field an operand is encoded into
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Nibble,
+    // Negative values down to -128 are stored as two's complement
+    Byte,
+    Address,
+    // Negative values down to -32768 are stored as two's complement
+    Word,
+}
+
+impl Field {
+    // Evaluate `expr` and check that it fits
+    pub fn value(self, expr: &Expr, symbols: &Symbols, span: Span) -> Result<u16, Diagnostic> {
+        let value = expr.eval(symbols, span)?;
+        let (min, max, help) = match self {
+            Field::Nibble => (0, 0xF, "nibbles go from 0 to 15 (0xF)"),
+            Field::Byte => (-0x80, 0xFF, "bytes go from -128 to 255 (0xFF)"),
+            Field::Address => (0, 0xFFF, "addresses go from 0 to 0xFFF"),
+            Field::Word => (-0x8000, 0xFFFF, "words go from -32768 to 65535 (0xFFFF)"),
+        };
+        if value < min || value > max {
+            return Err(
+                Diagnostic::new(format!("Value {} doesn't fit in {}", value, self), span)
+                    .with_help(String::from(help)),
+            );
+        }
+        Ok(value as u16 & max as u16)
+    }
+}
+
+impl std::fmt::Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Field::Nibble => "a nibble",
+            Field::Byte => "a byte",
+            Field::Address => "an address",
+            Field::Word => "a word",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+// One statement of the source, placed at its address by the first pass
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    pub address: u16,
+    // The value operand if there is one, where evaluation errors point
+    pub span: Span,
+}
+
+impl Instruction {
+    // Second pass: evaluate the operands and encode the instruction
+    pub fn encode(&self, symbols: &Symbols) -> Result<Vec<u8>, Diagnostic> {
+        self.opcode.encode(symbols, self.span)
+    }
+}
+
+// Registers are 0x0-0xF and checked by the parser, the other operands when encoding
+#[derive(Debug, Clone, PartialEq)]
 pub enum Opcode {
-    Cls
+    Cls,
+    Ret,
+    // SUPER-CHIP and XO-CHIP scrolling, exit and resolution
+    ScrollDown(Expr),
+    ScrollUp(Expr),
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    Low,
+    High,
+    Jump(Expr),
+    Call(Expr),
+    SkipEqByte(u8, Expr),
+    SkipNotEqByte(u8, Expr),
+    SkipEqReg(u8, u8),
+    SaveRange(u8, u8),
+    LoadRange(u8, u8),
+    LoadByte(u8, Expr),
+    AddByte(u8, Expr),
+    LoadReg(u8, u8),
+    Or(u8, u8),
+    And(u8, u8),
+    Xor(u8, u8),
+    AddReg(u8, u8),
+    Sub(u8, u8),
+    ShiftRight(u8, u8),
+    SubNotBorrow(u8, u8),
+    ShiftLeft(u8, u8),
+    SkipNotEqReg(u8, u8),
+    LoadI(Expr),
+    JumpV0(Expr),
+    Random(u8, Expr),
+    Draw(u8, u8, Expr),
+    SkipPress(u8),
+    SkipNotPress(u8),
+    LoadLong(Expr),
+    Plane(Expr),
+    Audio,
+    LoadDelay(u8),
+    WaitKey(u8),
+    SetDelay(u8),
+    SetSound(u8),
+    AddI(u8),
+    LoadSprite(u8),
+    LoadBigSprite(u8),
+    Bcd(u8),
+    Pitch(u8),
+    Store(u8),
+    Restore(u8),
+    StoreFlags(u8),
+    RestoreFlags(u8),
+    // A word which isn't an instruction, e.g. data written as a number by the disassembler
+    Raw(Expr),
+    // .TEXT, .SPRITE, .INCBIN and .ALIGN padding
+    Data(Vec<u8>),
+    // .DB
+    Byte(Expr),
+    // .DW
+    Word(Expr),
 }
 
+impl Opcode {
+    pub fn size(&self) -> u16 {
+        match self {
+            Opcode::LoadLong(_) => 4,
+            Opcode::Data(bytes) => bytes.len() as u16,
+            Opcode::Byte(_) => 1,
+            _ => 2,
+        }
+    }
+
+    fn encode(&self, symbols: &Symbols, span: Span) -> Result<Vec<u8>, Diagnostic> {
+        let value = |field: Field, expr: &Expr| field.value(expr, symbols, span);
+        let x_y = |prefix: u16, x: u8, y: u8, suffix: u16| {
+            prefix << 12 | (x as u16) << 8 | (y as u16) << 4 | suffix
+        };
+        let x_kk = |prefix: u16, x: u8, kk: u16| prefix << 12 | (x as u16) << 8 | kk;
+        let nnn =
+            |prefix: u16, expr: &Expr| value(Field::Address, expr).map(|nnn| prefix << 12 | nnn);
 
+        let opcode = match self {
+            Opcode::Cls => 0x00E0,
+            Opcode::Ret => 0x00EE,
+            Opcode::ScrollDown(n) => 0x00C0 | value(Field::Nibble, n)?,
+            Opcode::ScrollUp(n) => 0x00D0 | value(Field::Nibble, n)?,
+            Opcode::ScrollRight => 0x00FB,
+            Opcode::ScrollLeft => 0x00FC,
+            Opcode::Exit => 0x00FD,
+            Opcode::Low => 0x00FE,
+            Opcode::High => 0x00FF,
+            Opcode::Jump(target) => nnn(0x1, target)?,
+            Opcode::Call(target) => nnn(0x2, target)?,
+            Opcode::SkipEqByte(x, kk) => x_kk(0x3, *x, value(Field::Byte, kk)?),
+            Opcode::SkipNotEqByte(x, kk) => x_kk(0x4, *x, value(Field::Byte, kk)?),
+            Opcode::SkipEqReg(x, y) => x_y(0x5, *x, *y, 0x0),
+            Opcode::SaveRange(x, y) => x_y(0x5, *x, *y, 0x2),
+            Opcode::LoadRange(x, y) => x_y(0x5, *x, *y, 0x3),
+            Opcode::LoadByte(x, kk) => x_kk(0x6, *x, value(Field::Byte, kk)?),
+            Opcode::AddByte(x, kk) => x_kk(0x7, *x, value(Field::Byte, kk)?),
+            Opcode::LoadReg(x, y) => x_y(0x8, *x, *y, 0x0),
+            Opcode::Or(x, y) => x_y(0x8, *x, *y, 0x1),
+            Opcode::And(x, y) => x_y(0x8, *x, *y, 0x2),
+            Opcode::Xor(x, y) => x_y(0x8, *x, *y, 0x3),
+            Opcode::AddReg(x, y) => x_y(0x8, *x, *y, 0x4),
+            Opcode::Sub(x, y) => x_y(0x8, *x, *y, 0x5),
+            Opcode::ShiftRight(x, y) => x_y(0x8, *x, *y, 0x6),
+            Opcode::SubNotBorrow(x, y) => x_y(0x8, *x, *y, 0x7),
+            Opcode::ShiftLeft(x, y) => x_y(0x8, *x, *y, 0xE),
+            Opcode::SkipNotEqReg(x, y) => x_y(0x9, *x, *y, 0x0),
+            Opcode::LoadI(target) => {
+                nnn(0xA, target).map_err(|diagnostic| match target.eval(symbols, span) {
+                    Ok(address) if address > 0xFFF => diagnostic
+                        .with_help(String::from("use `LD I, LONG` for addresses above 0xFFF")),
+                    _ => diagnostic,
+                })?
+            }
+            Opcode::JumpV0(target) => nnn(0xB, target)?,
+            Opcode::Random(x, kk) => x_kk(0xC, *x, value(Field::Byte, kk)?),
+            Opcode::Draw(x, y, n) => x_y(0xD, *x, *y, value(Field::Nibble, n)?),
+            Opcode::SkipPress(x) => x_kk(0xE, *x, 0x9E),
+            Opcode::SkipNotPress(x) => x_kk(0xE, *x, 0xA1),
+            Opcode::LoadLong(target) => {
+                let address = value(Field::Word, target)?;
+                return Ok(vec![0xF0, 0x00, (address >> 8) as u8, address as u8]);
+            }
+            Opcode::Plane(n) => x_kk(0xF, value(Field::Nibble, n)? as u8, 0x01),
+            Opcode::Audio => 0xF002,
+            Opcode::LoadDelay(x) => x_kk(0xF, *x, 0x07),
+            Opcode::WaitKey(x) => x_kk(0xF, *x, 0x0A),
+            Opcode::SetDelay(x) => x_kk(0xF, *x, 0x15),
+            Opcode::SetSound(x) => x_kk(0xF, *x, 0x18),
+            Opcode::AddI(x) => x_kk(0xF, *x, 0x1E),
+            Opcode::LoadSprite(x) => x_kk(0xF, *x, 0x29),
+            Opcode::LoadBigSprite(x) => x_kk(0xF, *x, 0x30),
+            Opcode::Bcd(x) => x_kk(0xF, *x, 0x33),
+            Opcode::Pitch(x) => x_kk(0xF, *x, 0x3A),
+            Opcode::Store(x) => x_kk(0xF, *x, 0x55),
+            Opcode::Restore(x) => x_kk(0xF, *x, 0x65),
+            Opcode::StoreFlags(x) => x_kk(0xF, *x, 0x75),
+            Opcode::RestoreFlags(x) => x_kk(0xF, *x, 0x85),
+            Opcode::Raw(word) | Opcode::Word(word) => value(Field::Word, word)?,
+            Opcode::Data(bytes) => return Ok(bytes.clone()),
+            Opcode::Byte(byte) => return Ok(vec![value(Field::Byte, byte)? as u8]),
+        };
+        Ok(opcode.to_be_bytes().to_vec())
+    }
+}
@@ -1,3 +1,6 @@
+use std::num::IntErrorKind;
+
+use super::diagnostic::{Diagnostic, Span};
 use super::token::{Token, TokenType};
 
 pub(crate) struct Lexer {
@@ -6,16 +9,22 @@ pub(crate) struct Lexer {
     start: usize,
     current: usize,
     line: usize,
+    // Index of the first character of the current line
+    line_start: usize,
+    file: usize,
 }
 
 impl Lexer {
-    pub fn new(source: &str) -> Self {
+    // `file` is the index of the source in `Sources`
+    pub fn new(source: &str, file: usize) -> Self {
         Self {
             source: source.chars().collect(),
             tokens: Vec::new(),
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            file,
         }
     }
 
@@ -24,10 +33,8 @@ impl Lexer {
             self.start = self.current;
             self.scan_token();
         }
-        self.tokens.push(Token {
-            token_type: TokenType::Eof,
-            line: self.line,
-        });
+        self.start = self.current;
+        self.add_token(TokenType::Eof);
     }
 
     pub fn tokens(self) -> Vec<Token> {
@@ -37,15 +44,43 @@ impl Lexer {
     fn scan_token(&mut self) {
         let c = self.advance();
         match c {
-            ' ' | '\t' | '\r' | ',' => {}
-            '\n' => self.line += 1,
+            ' ' | '\t' | '\r' => {}
+            '\n' => {
+                self.add_token(TokenType::Newline);
+                self.line += 1;
+                self.line_start = self.current;
+            }
             ';' => self.comment(),
             '.' => self.directive(),
-            'v' | 'V' if self.peek().is_digit(10) => self.register_v(),
+            '[' => self.indirect(),
+            '"' => self.string(),
+            'v' | 'V' if self.peek().is_ascii_digit() => self.register_v(),
             ':' => self.add_token(TokenType::Assign),
-            x if x.is_digit(10) => self.number(),
-            x if x.is_ascii_alphabetic() => self.symbol(),
-            _ => self.add_token(TokenType::Error(format!("Unrecognized token `{}` line {}", c, self.line))),
+            ',' => self.add_token(TokenType::Comma),
+            '(' => self.add_token(TokenType::LeftParen),
+            ')' => self.add_token(TokenType::RightParen),
+            '+' => self.add_token(TokenType::Plus),
+            '-' => self.add_token(TokenType::Minus),
+            '*' => self.add_token(TokenType::Star),
+            '/' => self.add_token(TokenType::Slash),
+            '%' => self.add_token(TokenType::Percent),
+            '&' => self.add_token(TokenType::Ampersand),
+            '|' => self.add_token(TokenType::Pipe),
+            '^' => self.add_token(TokenType::Caret),
+            '~' => self.add_token(TokenType::Tilde),
+            '<' if self.peek() == '<' => {
+                self.advance();
+                self.add_token(TokenType::Shl)
+            }
+            '>' if self.peek() == '>' => {
+                self.advance();
+                self.add_token(TokenType::Shr)
+            }
+            '#' | '$' if self.peek().is_ascii_hexdigit() => self.number(),
+            x if x.is_ascii_digit() => self.number(),
+            // `@` starts the labels local to a macro
+            x if x.is_ascii_alphabetic() || x == '_' || x == '@' => self.symbol(),
+            _ => self.error(format!("Unrecognized character `{}`", c), None),
         }
     }
 
@@ -58,17 +93,38 @@ impl Lexer {
     fn add_token(&mut self, token_type: TokenType) {
         let token = Token {
             token_type,
-            line: self.line,
+            span: self.span(),
         };
         self.tokens.push(token);
     }
 
+    // Span of the token being scanned
+    fn span(&self) -> Span {
+        Span {
+            file: self.file,
+            line: self.line,
+            column: self.start - self.line_start + 1,
+            length: self.current - self.start,
+        }
+    }
+
+    fn error(&mut self, message: String, help: Option<String>) {
+        let mut diagnostic = Diagnostic::new(message, self.span());
+        diagnostic.help = help;
+        self.add_token(TokenType::Error(diagnostic));
+    }
+
+    // '\0' past the end of the source
     fn peek(&self) -> char {
-        self.source[self.current]
+        self.source.get(self.current).copied().unwrap_or('\0')
+    }
+
+    fn is_word_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '_'
     }
 
     fn comment(&mut self) {
-        while self.peek() != '\n' {
+        while self.peek() != '\n' && self.peek() != '\0' {
             self.advance();
         }
     }
@@ -78,34 +134,119 @@ impl Lexer {
             self.advance();
         }
 
-        let directive_str: String = self.source[self.start + 1 .. self.current].iter().collect();
+        let directive_str: String = self.source[self.start + 1..self.current].iter().collect();
 
         self.add_token(TokenType::Directive(directive_str));
     }
 
-    fn scan_number(&mut self, start: usize) -> u16 {
-        while self.peek().is_digit(10) {
-            self.advance();
+    // Double-quoted, on a single line, with \\, \" and \n escapes
+    fn string(&mut self) {
+        let mut text = String::new();
+        loop {
+            match self.peek() {
+                '"' => {
+                    self.advance();
+                    self.add_token(TokenType::Str(text));
+                    return;
+                }
+                '\n' | '\0' => {
+                    return self.error(
+                        String::from("Unterminated string"),
+                        Some(String::from("strings end with `\"` on the same line")),
+                    );
+                }
+                '\\' => {
+                    self.advance();
+                    let escaped = match self.peek() {
+                        '\\' => '\\',
+                        '"' => '"',
+                        'n' => '\n',
+                        c => {
+                            return self.error(
+                                format!("Unknown escape `\\{}`", c.escape_default()),
+                                Some(String::from("use `\\\\`, `\\\"` or `\\n`")),
+                            );
+                        }
+                    };
+                    self.advance();
+                    text.push(escaped);
+                }
+                c => {
+                    self.advance();
+                    text.push(c);
+                }
+            }
         }
+    }
 
-        let num_string: String = self.source[start..self.current].iter().collect();
-        num_string.parse().unwrap()
+    // [I], the only bracketed operand
+    fn indirect(&mut self) {
+        let text: String = self.source[self.current..]
+            .iter()
+            .take(2)
+            .collect::<String>()
+            .to_uppercase();
+        if text == "I]" {
+            self.current += 2;
+            self.add_token(TokenType::IndirectI);
+        } else {
+            self.error(
+                String::from("Expected `[I]`"),
+                Some(String::from("`[I]` is the only bracketed operand")),
+            );
+        }
     }
 
     fn register_v(&mut self) {
-        let parsed_num = self.scan_number(self.start + 1);
-
-        self.add_token(TokenType::RegV(parsed_num as u8));
+        while self.peek().is_ascii_digit() {
+            self.advance();
+        }
+        let digits: String = self.source[self.start + 1..self.current].iter().collect();
+        match digits.parse::<u8>() {
+            Ok(x) if x < 16 => self.add_token(TokenType::RegV(x)),
+            _ => self.error(
+                format!("Invalid register `V{}`", digits),
+                Some(format!(
+                    "`V{}` is not a register, did you mean `VF`? Registers go from V0 to VF",
+                    digits
+                )),
+            ),
+        }
     }
 
+    // Decimal, hexadecimal with a 0x, # or $ prefix, or binary with a 0b prefix
     fn number(&mut self) {
-        let parsed_num = self.scan_number(self.start);
+        while Self::is_word_char(self.peek()) {
+            self.advance();
+        }
 
-        self.add_token(TokenType::Number(parsed_num));
+        let text: String = self.source[self.start..self.current].iter().collect();
+        let lower = text.to_lowercase();
+        let parsed = if let Some(digits) = lower
+            .strip_prefix("0x")
+            .or_else(|| lower.strip_prefix('#'))
+            .or_else(|| lower.strip_prefix('$'))
+        {
+            u16::from_str_radix(digits, 16)
+        } else if let Some(digits) = lower.strip_prefix("0b") {
+            u16::from_str_radix(digits, 2)
+        } else {
+            lower.parse()
+        };
+        match parsed {
+            Ok(number) => self.add_token(TokenType::Number(number)),
+            Err(e) => {
+                let help = match e.kind() {
+                    IntErrorKind::PosOverflow => "numbers must fit in 16 bits (at most 65535)",
+                    _ => "numbers are decimal, hexadecimal with a `0x`, `#` or `$` prefix, or binary with a `0b` prefix",
+                };
+                self.error(format!("Invalid number `{}`", text), Some(help.to_string()))
+            }
+        }
     }
 
     fn symbol(&mut self) {
-        while self.peek().is_alphanumeric() {
+        while Self::is_word_char(self.peek()) {
             self.advance();
         }
 
@@ -120,6 +261,7 @@ impl Lexer {
             "LD" => TokenType::Load,
             "ADD" => TokenType::Add,
             "OR" => TokenType::Or,
+            "AND" => TokenType::And,
             "XOR" => TokenType::Xor,
             "SUB" => TokenType::Sub,
             "SHR" => TokenType::ShiftRight,
@@ -129,12 +271,34 @@ impl Lexer {
             "DRW" => TokenType::Draw,
             "SKP" => TokenType::SkipPress,
             "SKNP" => TokenType::SkipNotPress,
+            "SCD" => TokenType::ScrollDown,
+            "SCU" => TokenType::ScrollUp,
+            "SCR" => TokenType::ScrollRight,
+            "SCL" => TokenType::ScrollLeft,
+            "EXIT" => TokenType::Exit,
+            "LOW" => TokenType::Low,
+            "HIGH" => TokenType::High,
+            "SAVE" => TokenType::SaveRange,
+            "LOAD" => TokenType::LoadRange,
+            "PLANE" => TokenType::Plane,
+            "AUDIO" => TokenType::Audio,
+            "PITCH" => TokenType::Pitch,
+            "LONG" => TokenType::Long,
             "I" => TokenType::RegI,
             "K" => TokenType::Key,
             "ST" => TokenType::SoundTimer,
             "DT" => TokenType::DelayTimer,
             "B" => TokenType::Bcd,
             "F" => TokenType::Sprite,
+            "HF" => TokenType::BigSprite,
+            "R" => TokenType::Flags,
+            // VA-VF, V0-V15 are scanned as numbers
+            upper if upper.len() == 2 && upper.starts_with('V') => {
+                match u8::from_str_radix(&upper[1..], 16) {
+                    Ok(x) => TokenType::RegV(x),
+                    Err(_) => TokenType::Label(text),
+                }
+            }
             _ => TokenType::Label(text),
         };
         self.add_token(token);
@@ -1,11 +1,22 @@
+mod diagnostic;
+mod expression;
+mod instruction;
 mod lexer;
 mod parser;
+mod source;
 mod token;
-mod instruction;
+
+#[cfg(test)]
+mod tests;
 
 use std::fs;
+use std::path::Path;
 
 use crate::assembler::lexer::Lexer;
+use crate::assembler::parser::Parser;
+
+pub use crate::assembler::diagnostic::{Diagnostic, Span};
+pub use crate::assembler::source::{AssembleErrors, SourceFile, Sources};
 
 pub struct Assembler {
     source_path: String,
@@ -22,11 +33,53 @@ impl Assembler {
         }
     }
 
-    pub fn run(&self) {
+    // Writes the program to `<file_name>_a.ch8`, returns false if it doesn't assemble
+    pub fn run(&self) -> bool {
         println!("Running assembler");
-        let mut lexer = Lexer::new(&self.source_code);
-        lexer.scan_tokens();
-        let tokens = lexer.tokens();
-        dbg!(tokens);
+        let bytes = match assemble(Path::new(&self.source_path), &self.source_code) {
+            Ok(bytes) => bytes,
+            Err(errors) => {
+                eprintln!("{}\n", errors);
+                let count = errors.diagnostics.len();
+                eprintln!(
+                    "Could not assemble {}: {} error{}",
+                    self.source_path,
+                    count,
+                    if count == 1 { "" } else { "s" }
+                );
+                return false;
+            }
+        };
+        let file_name = self.parse_path();
+        match fs::write(Path::new(&file_name), &bytes) {
+            Ok(()) => {
+                println!("File assembled: {} ({} bytes)", file_name, bytes.len());
+                true
+            }
+            Err(e) => {
+                println!("Error: {}", e);
+                false
+            }
+        }
     }
+
+    fn parse_path(&self) -> String {
+        let file_name: Vec<_> = self.source_path.split(".chasm").collect();
+        format!("{}_a.ch8", file_name[0])
+    }
+}
+
+// Assemble the source code of the file at `path` into the bytes of a program loaded
+// at 0x200, or every error found, sorted by position.
+// Included files are looked up relative to the file including them.
+pub fn assemble(path: &Path, source_code: &str) -> Result<Vec<u8>, AssembleErrors> {
+    let mut sources = Sources::default();
+    let file = sources.add(path.to_path_buf(), source_code.to_string(), None);
+    let mut lexer = Lexer::new(source_code, file);
+    lexer.scan_tokens();
+    let result = Parser::new(&mut sources, lexer.tokens()).assemble();
+    result.map_err(|diagnostics| AssembleErrors {
+        diagnostics,
+        sources,
+    })
 }
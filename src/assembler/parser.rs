@@ -1,25 +1,1018 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::fs;
 
-use super::{instruction::Instruction, token::Token, token::TokenType};
+use super::diagnostic::{closest, Diagnostic, Span};
+use super::expression::{self, Expr, Symbol, Symbols};
+use super::instruction::{Instruction, Opcode};
+use super::lexer::Lexer;
+use super::source::{directory, Sources};
+use super::token::{Token, TokenType};
 
-struct Parser {
-    sym_table: HashMap<String, u16>,
-    tokens: Vec<Token>,
-    instructions: Vec<Instruction>
-}
+// Programs are loaded after the interpreter area
+pub const START_ADDRESS: u16 = 0x200;
 
+// The address space, .ORIG may place code anywhere after START_ADDRESS
+const MEMORY_END: usize = 0x10000;
 
+const DIRECTIVES: [&str; 12] = [
+    "DB", "DW", "TEXT", "SPRITE", "ALIGN", "ORIG", "EQU", "DEFINE", "INCLUDE", "INCBIN", "MACRO",
+    "ENDM",
+];
+
+// Operand forms of each mnemonic, for suggestions
+const FORMS: [(&str, &[&str]); 31] = [
+    ("CLS", &["CLS"]),
+    ("RET", &["RET"]),
+    ("JP", &["JP addr", "JP V0, addr"]),
+    ("CALL", &["CALL addr"]),
+    ("SE", &["SE Vx, byte", "SE Vx, Vy"]),
+    ("SNE", &["SNE Vx, byte", "SNE Vx, Vy"]),
+    (
+        "LD",
+        &[
+            "LD Vx, byte",
+            "LD Vx, Vy",
+            "LD I, addr",
+            "LD I, LONG addr",
+            "LD Vx, DT",
+            "LD Vx, K",
+            "LD DT, Vx",
+            "LD ST, Vx",
+            "LD F, Vx",
+            "LD HF, Vx",
+            "LD B, Vx",
+            "LD [I], Vx",
+            "LD Vx, [I]",
+            "LD R, Vx",
+            "LD Vx, R",
+        ],
+    ),
+    ("ADD", &["ADD Vx, byte", "ADD Vx, Vy", "ADD I, Vx"]),
+    ("OR", &["OR Vx, Vy"]),
+    ("AND", &["AND Vx, Vy"]),
+    ("XOR", &["XOR Vx, Vy"]),
+    ("SUB", &["SUB Vx, Vy"]),
+    ("SUBN", &["SUBN Vx, Vy"]),
+    ("SHR", &["SHR Vx", "SHR Vx, Vy"]),
+    ("SHL", &["SHL Vx", "SHL Vx, Vy"]),
+    ("RND", &["RND Vx, byte"]),
+    ("DRW", &["DRW Vx, Vy, nibble"]),
+    ("SKP", &["SKP Vx"]),
+    ("SKNP", &["SKNP Vx"]),
+    ("SCD", &["SCD nibble"]),
+    ("SCU", &["SCU nibble"]),
+    ("SCR", &["SCR"]),
+    ("SCL", &["SCL"]),
+    ("EXIT", &["EXIT"]),
+    ("LOW", &["LOW"]),
+    ("HIGH", &["HIGH"]),
+    ("SAVE", &["SAVE Vx, Vy"]),
+    ("LOAD", &["LOAD Vx, Vy"]),
+    ("PLANE", &["PLANE nibble"]),
+    ("AUDIO", &["AUDIO"]),
+    ("PITCH", &["PITCH Vx"]),
+];
 
-// Set up Instruction structs
-//
 // First pass:
-//  parse directives directly (i.e. set offset to .ORIG value)
-//  line by line, match first element, expect other elements to build instructions
-//  store LabelAssign line number in symbol table
+//  one statement per line: an optional `LABEL:`, then an instruction and its operands,
+//  a directive, a macro invocation, or an expression alone emitted as a raw 16-bit word.
+//  Each instruction gets its address and each label the address of what follows it.
+//  Included files and macro expansions replace the statement which brought them in.
 // Second pass:
-//  Update Label References to line number + offset 
-//
-// Emit bytecode:
-//  Convert Instruction to 16-bit bytecode
-//  Push bytes to chunk
-//  Write chunk to .ch8 file
+//  evaluate the operands and encode the instructions, see `Instruction::encode`.
+// Errors don't stop either pass: every statement is checked and all errors are returned.
+pub struct Parser<'a> {
+    sources: &'a mut Sources,
+    symbols: Symbols,
+    // Where each label, constant and alias is defined
+    definitions: HashMap<String, Span>,
+    // .DEFINE aliases, replaced by their tokens in operands
+    aliases: HashMap<String, Vec<TokenType>>,
+    macros: HashMap<String, Macro>,
+    // The macros being expanded, innermost last
+    expanding: Vec<String>,
+    // Number of expansions so far, to make local labels unique
+    expansions: usize,
+    tokens: Vec<Token>,
+    instructions: Vec<Instruction>,
+    errors: Vec<Diagnostic>,
+    current: usize,
+    // Where the next instruction goes, may be MEMORY_END once memory is full
+    address: usize,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+// An operand, between commas
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    // Registers and the other reserved words: V3, I, [I], DT, ST, K, F, HF, B, R
+    Keyword(TokenType),
+    Value(Expr),
+    // LONG followed by an address
+    LongValue(Expr),
+}
+
+impl<'a> Parser<'a> {
+    // `sources` holds the file `tokens` come from, the included ones are added to it
+    pub fn new(sources: &'a mut Sources, tokens: Vec<Token>) -> Self {
+        Self {
+            sources,
+            symbols: Symbols::new(),
+            definitions: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            expanding: Vec::new(),
+            expansions: 0,
+            tokens,
+            instructions: Vec::new(),
+            errors: Vec::new(),
+            current: 0,
+            address: START_ADDRESS as usize,
+        }
+    }
+
+    // Both passes, returning the program bytes or every error found.
+    // Gaps left by .ORIG are filled with zeros.
+    pub fn assemble(mut self) -> Result<Vec<u8>, Vec<Diagnostic>> {
+        self.parse();
+        let mut bytes = Vec::new();
+        let mut written = Vec::new();
+        for instruction in self.instructions.iter() {
+            let encoded = match instruction.encode(&self.symbols) {
+                Ok(encoded) => encoded,
+                Err(diagnostic) => {
+                    self.errors.push(diagnostic);
+                    continue;
+                }
+            };
+            let start = (instruction.address - START_ADDRESS) as usize;
+            let end = start + encoded.len();
+            if end > bytes.len() {
+                bytes.resize(end, 0);
+                written.resize(end, false);
+            }
+            if let Some(overlap) = written[start..end].iter().position(|&taken| taken) {
+                self.errors.push(
+                    Diagnostic::new(
+                        format!(
+                            "Overlaps code already assembled at {:#05X}",
+                            instruction.address as usize + overlap
+                        ),
+                        instruction.span,
+                    )
+                    .with_help(String::from("check the `.ORIG` directives")),
+                );
+                continue;
+            }
+            bytes[start..end].copy_from_slice(&encoded);
+            written[start..end]
+                .iter_mut()
+                .for_each(|taken| *taken = true);
+        }
+        if self.errors.is_empty() {
+            Ok(bytes)
+        } else {
+            self.errors
+                .sort_by_key(|error| (error.span.file, error.span.line, error.span.column));
+            Err(self.errors)
+        }
+    }
+
+    fn parse(&mut self) {
+        loop {
+            match self.peek().token_type {
+                TokenType::Eof => break,
+                TokenType::Newline => {}
+                TokenType::MacroEnd => {
+                    self.expanding.pop();
+                }
+                _ => {
+                    self.statement();
+                    continue;
+                }
+            }
+            self.advance();
+        }
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.current]
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.current].clone();
+        if token.token_type != TokenType::Eof {
+            self.current += 1;
+        }
+        token
+    }
+
+    fn at_end_of_line(&self) -> bool {
+        matches!(
+            self.peek().token_type,
+            TokenType::Newline | TokenType::MacroEnd | TokenType::Eof
+        )
+    }
+
+    // The remaining tokens of a line
+    fn rest_of_line(&mut self) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        while !self.at_end_of_line() {
+            tokens.push(self.advance());
+        }
+        tokens
+    }
+
+    // The operand tokens of a statement, or the first lexing error among them
+    fn operands(&mut self) -> Result<Vec<Token>, Diagnostic> {
+        let operands = self.rest_of_line();
+        match operands
+            .iter()
+            .find_map(|operand| match &operand.token_type {
+                TokenType::Error(diagnostic) => Some(diagnostic.clone()),
+                _ => None,
+            }) {
+            Some(diagnostic) => Err(diagnostic),
+            None => Ok(operands),
+        }
+    }
+
+    // Replace the aliases by their tokens, in parentheses if there are several
+    // so that `.DEFINE TWO 1 + 1` followed by `TWO * 2` gives 4
+    fn expand_aliases(&self, tokens: Vec<Token>) -> Vec<Token> {
+        let mut expanded = Vec::new();
+        for token in tokens {
+            let alias = match &token.token_type {
+                TokenType::Label(name) => self.aliases.get(name),
+                _ => None,
+            };
+            let replacement = match alias {
+                Some(replacement) => replacement,
+                None => {
+                    expanded.push(token);
+                    continue;
+                }
+            };
+            let at = |token_type: TokenType| Token {
+                token_type,
+                span: token.span,
+            };
+            if replacement.len() > 1 {
+                expanded.push(at(TokenType::LeftParen));
+            }
+            expanded.extend(replacement.iter().cloned().map(at));
+            if replacement.len() > 1 {
+                expanded.push(at(TokenType::RightParen));
+            }
+        }
+        expanded
+    }
+
+    // Checks that `name` is new before defining a label, constant or alias
+    fn define(&mut self, name: &str, span: Span) -> Result<(), Diagnostic> {
+        match self.definitions.get(name) {
+            Some(first) => Err(
+                Diagnostic::new(format!("`{}` is already defined", name), span).with_help(format!(
+                    "first defined at {}",
+                    self.sources.location(*first)
+                )),
+            ),
+            None => {
+                self.definitions.insert(name.to_string(), span);
+                Ok(())
+            }
+        }
+    }
+
+    fn statement(&mut self) {
+        let token = self.advance();
+        let result = match token.token_type.clone() {
+            TokenType::Label(name) if self.peek().token_type == TokenType::Assign => {
+                self.advance();
+                self.define(&name, token.span).map(|()| {
+                    self.symbols.insert(name, Symbol::Label(self.address));
+                })
+            }
+            TokenType::Label(name) if self.macros.contains_key(&name) => self
+                .operands()
+                .and_then(|operands| self.expand_macro(&name, token.span, operands)),
+            TokenType::Label(name) if !self.aliases.contains_key(&name) => {
+                let operands = self.rest_of_line();
+                let diagnostic =
+                    Diagnostic::new(format!("Unknown instruction `{}`", name), token.span);
+                Err(
+                    match closest(&name, FORMS.iter().map(|(mnemonic, _)| *mnemonic)) {
+                        Some(mnemonic) => {
+                            diagnostic.with_help(format!("did you mean `{}`?", mnemonic))
+                        }
+                        None if operands.is_empty() => diagnostic
+                            .with_help(format!("add a colon to define a label: `{}:`", name)),
+                        None => diagnostic,
+                    },
+                )
+            }
+            TokenType::Error(diagnostic) => {
+                self.rest_of_line();
+                Err(diagnostic)
+            }
+            TokenType::Directive(name) => self
+                .operands()
+                .and_then(|operands| self.directive(&name, token.span, operands)),
+            // An expression alone, e.g. a number written by the disassembler
+            TokenType::Number(_)
+            | TokenType::Label(_)
+            | TokenType::LeftParen
+            | TokenType::Minus
+            | TokenType::Plus
+            | TokenType::Tilde => self.operands().and_then(|operands| {
+                let mut tokens = vec![token.clone()];
+                tokens.extend(operands);
+                let tokens = self.expand_aliases(tokens);
+                let span = tokens[0].span.to(tokens[tokens.len() - 1].span);
+                let word = expression::parse(&tokens)?;
+                self.emit(Opcode::Raw(word), span);
+                Ok(())
+            }),
+            _ => self.operands().and_then(|operands| {
+                let operands = self.expand_aliases(operands);
+                let span = match (operands.first(), operands.last()) {
+                    (Some(first), Some(last)) => first.span.to(last.span),
+                    _ => token.span,
+                };
+                let parsed = split_operands(&operands)?;
+                let values: Vec<Span> = parsed
+                    .iter()
+                    .filter(|(operand, _)| !matches!(operand, Operand::Keyword(_)))
+                    .map(|(_, span)| *span)
+                    .collect();
+                let kinds: Vec<Operand> = parsed.into_iter().map(|(operand, _)| operand).collect();
+                let opcode = parse_instruction(&token.token_type, &kinds)
+                    .ok_or_else(|| invalid_operands(&token, operands.is_empty(), span))?;
+                // Evaluation errors point at the value operand, there is at most one
+                self.emit(opcode, values.first().copied().unwrap_or(token.span));
+                Ok(())
+            }),
+        };
+        if let Err(diagnostic) = result {
+            self.errors.push(diagnostic);
+        }
+    }
+
+    fn emit(&mut self, opcode: Opcode, span: Span) {
+        let end = self.address + opcode.size() as usize;
+        if end > MEMORY_END {
+            self.errors.push(
+                Diagnostic::new(format!("{:#06X} is past the end of memory", end - 1), span)
+                    .with_help(String::from("the address space ends at 0xFFFF")),
+            );
+            return;
+        }
+        // Empty data has nothing to place, and may be at the very end of memory
+        if end > self.address {
+            self.instructions.push(Instruction {
+                opcode,
+                address: self.address as u16,
+                span,
+            });
+        }
+        self.address = end;
+    }
+
+    // Value of a .ALIGN or .ORIG operand, which is needed during the first pass
+    fn eval_now(&self, expr: &Expr, span: Span) -> Result<i64, Diagnostic> {
+        expr.eval(&self.symbols, span).map_err(|diagnostic| {
+            diagnostic.with_help(String::from(
+                "the labels and constants used here must be defined before this line",
+            ))
+        })
+    }
+
+    fn directive(
+        &mut self,
+        name: &str,
+        span: Span,
+        operands: Vec<Token>,
+    ) -> Result<(), Diagnostic> {
+        let upper = name.to_uppercase();
+        let expects = |what: &str| Diagnostic::new(format!("`.{}` expects {}", upper, what), span);
+        // Strings can't be part of expressions, .TEXT and .SPRITE look at the tokens
+        let strings = || {
+            operands
+                .iter()
+                .filter(|operand| operand.token_type != TokenType::Comma)
+                .map(|operand| (&operand.token_type, operand.span))
+        };
+        match upper.as_str() {
+            "DB" | "DW" => {
+                let values = split_operands(&self.expand_aliases(operands.clone()))?;
+                if values.is_empty() {
+                    return Err(expects("at least one value"));
+                }
+                // One instruction per value, so that errors point to the right operand
+                for (value, span) in values {
+                    let opcode = match (value, upper.as_str()) {
+                        (Operand::Value(expr), "DB") => Opcode::Byte(expr),
+                        (Operand::Value(expr), _) => Opcode::Word(expr),
+                        (other, _) => {
+                            return Err(Diagnostic::new(
+                                format!("Expected a value, found `{}`", other),
+                                span,
+                            ))
+                        }
+                    };
+                    self.emit(opcode, span);
+                }
+            }
+            "TEXT" => {
+                if operands.is_empty() {
+                    return Err(expects("at least one string"));
+                }
+                let mut bytes = Vec::new();
+                for (value, span) in strings() {
+                    match value {
+                        TokenType::Str(text) if text.is_ascii() => bytes.extend(text.bytes()),
+                        TokenType::Str(_) => {
+                            return Err(Diagnostic::new(String::from("Non-ASCII text"), span)
+                                .with_help(String::from(
+                                    "strings are stored one byte per character",
+                                )))
+                        }
+                        other => return Err(unexpected(other, "a string", span)),
+                    }
+                }
+                self.emit(Opcode::Data(bytes), span);
+            }
+            "SPRITE" => {
+                if operands.is_empty() {
+                    return Err(expects("at least one row, e.g. \"#..##..#\""));
+                }
+                let mut bytes = Vec::new();
+                let mut width = None;
+                for (value, span) in strings() {
+                    let row = match value {
+                        TokenType::Str(row) => row,
+                        other => return Err(unexpected(other, "a row of `#` and `.`", span)),
+                    };
+                    if *width.get_or_insert(row.len()) != row.len() {
+                        return Err(Diagnostic::new(
+                            String::from("Sprite rows have different widths"),
+                            span,
+                        )
+                        .with_help(format!(
+                            "the first row is {} pixels wide",
+                            width.unwrap_or(0)
+                        )));
+                    }
+                    bytes.extend(sprite_row(row, span)?);
+                }
+                self.emit(Opcode::Data(bytes), span);
+            }
+            "ALIGN" => {
+                let alignment = match split_operands(&self.expand_aliases(operands))?.as_slice() {
+                    [] => 2,
+                    [(Operand::Value(expr), span)] => match self.eval_now(expr, *span)? {
+                        n if n > 0 && n <= 0x8000 && (n as u16).is_power_of_two() => n as usize,
+                        n => {
+                            return Err(Diagnostic::new(
+                                format!("Expected a power of two, found {}", n),
+                                *span,
+                            ))
+                        }
+                    },
+                    _ => return Err(expects("at most one alignment")),
+                };
+                let padding = (alignment - self.address % alignment) % alignment;
+                if padding > 0 {
+                    self.emit(Opcode::Data(vec![0; padding]), span);
+                }
+            }
+            "ORIG" => {
+                self.address = match split_operands(&self.expand_aliases(operands))?.as_slice() {
+                    [] => START_ADDRESS as usize,
+                    [(Operand::Value(expr), span)] => match self.eval_now(expr, *span)? {
+                        address if (START_ADDRESS as i64..MEMORY_END as i64).contains(&address) => {
+                            address as usize
+                        }
+                        address => {
+                            return Err(Diagnostic::new(
+                                format!("Address {:#05X} is outside the program", address),
+                                *span,
+                            )
+                            .with_help(String::from("programs go from 0x200 to 0xFFFF")))
+                        }
+                    },
+                    _ => return Err(expects("at most one address")),
+                };
+            }
+            "EQU" | "DEFINE" => {
+                let mut tokens = operands.into_iter();
+                let (name, name_span) = match tokens.next() {
+                    Some(Token {
+                        token_type: TokenType::Label(name),
+                        span,
+                    }) => (name, span),
+                    Some(token) => return Err(unexpected(&token.token_type, "a name", token.span)),
+                    None => return Err(expects("a name and a value, e.g. `WIDTH, 64`")),
+                };
+                let mut value: Vec<Token> = tokens.collect();
+                if value.first().map(|token| &token.token_type) == Some(&TokenType::Comma) {
+                    value.remove(0);
+                }
+                if value.is_empty() {
+                    return Err(expects("a value after the name"));
+                }
+                if upper == "EQU" {
+                    let expr = expression::parse(&self.expand_aliases(value))?;
+                    self.define(&name, name_span)?;
+                    self.symbols.insert(name, Symbol::Constant(expr));
+                } else {
+                    let value = self.expand_aliases(value);
+                    self.define(&name, name_span)?;
+                    self.aliases.insert(
+                        name,
+                        value.into_iter().map(|token| token.token_type).collect(),
+                    );
+                }
+            }
+            "INCLUDE" => {
+                let (name, name_span) = path_operand(&operands, expects("a file name"))?;
+                let path = directory(&self.sources.get(span.file).path).join(&name);
+                // Compare the canonical paths, `a.chasm` and `./a.chasm` are the same file
+                if let Ok(canonical) = fs::canonicalize(&path) {
+                    let chain: Vec<_> = self.sources.chain(span.file).collect();
+                    if chain
+                        .iter()
+                        .any(|file| fs::canonicalize(&file.path).ok().as_ref() == Some(&canonical))
+                    {
+                        let names: Vec<String> = chain
+                            .iter()
+                            .rev()
+                            .map(|file| file.path.display().to_string())
+                            .collect();
+                        return Err(Diagnostic::new(
+                            format!("`{}` includes itself", name),
+                            name_span,
+                        )
+                        .with_help(format!(
+                            "{} -> {}",
+                            names.join(" -> "),
+                            path.display()
+                        )));
+                    }
+                }
+                let text = fs::read_to_string(&path).map_err(|e| {
+                    Diagnostic::new(
+                        format!("Unable to read `{}`: {}", path.display(), e),
+                        name_span,
+                    )
+                })?;
+                let file = self.sources.add(path, text, Some(span));
+                let mut lexer = Lexer::new(&self.sources.get(file).text, file);
+                lexer.scan_tokens();
+                let mut tokens = lexer.tokens();
+                tokens.pop(); // Eof
+                self.tokens.splice(self.current..self.current, tokens);
+            }
+            "INCBIN" => {
+                let (name, name_span) = path_operand(&operands, expects("a file name"))?;
+                let path = directory(&self.sources.get(span.file).path).join(&name);
+                let bytes = fs::read(&path).map_err(|e| {
+                    Diagnostic::new(
+                        format!("Unable to read `{}`: {}", path.display(), e),
+                        name_span,
+                    )
+                })?;
+                if bytes.len() > MEMORY_END - START_ADDRESS as usize {
+                    return Err(Diagnostic::new(
+                        format!("`{}` is too large, {} bytes", name, bytes.len()),
+                        name_span,
+                    ));
+                }
+                self.emit(Opcode::Data(bytes), span);
+            }
+            "MACRO" => {
+                // Skip the body even if the first line is wrong
+                let header = macro_header(
+                    &operands,
+                    expects("a name and parameters, e.g. `ADD16 hi, lo`"),
+                );
+                let body = self.macro_body(span)?;
+                let (name, name_span, params) = header?;
+                self.define(&name, name_span)?;
+                self.macros.insert(name, Macro { params, body });
+            }
+            "ENDM" => {
+                return Err(Diagnostic::new(
+                    String::from("`.ENDM` without `.MACRO`"),
+                    span,
+                ))
+            }
+            _ => {
+                let diagnostic = Diagnostic::new(format!("Unknown directive `.{}`", name), span);
+                return Err(match closest(name, DIRECTIVES.iter().copied()) {
+                    Some(directive) => {
+                        diagnostic.with_help(format!("did you mean `.{}`?", directive))
+                    }
+                    None => diagnostic.with_help(format!(
+                        "directives are {}",
+                        DIRECTIVES
+                            .iter()
+                            .map(|directive| format!("`.{}`", directive))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Parser<'_> {
+    // The tokens up to `.ENDM`, which is consumed
+    fn macro_body(&mut self, span: Span) -> Result<Vec<Token>, Diagnostic> {
+        let mut body = Vec::new();
+        loop {
+            match &self.peek().token_type {
+                TokenType::Eof | TokenType::MacroEnd => {
+                    return Err(Diagnostic::new(String::from("Missing `.ENDM`"), span)
+                        .with_help(String::from("macros end with a `.ENDM` line")))
+                }
+                TokenType::Directive(name) if name.eq_ignore_ascii_case("ENDM") => {
+                    self.advance();
+                    return match self.rest_of_line().first() {
+                        Some(extra) => Err(unexpected(&extra.token_type, "a new line", extra.span)),
+                        None => Ok(body),
+                    };
+                }
+                TokenType::Directive(name) if name.eq_ignore_ascii_case("MACRO") => {
+                    return Err(Diagnostic::new(
+                        String::from("Macros can't be defined inside macros"),
+                        self.peek().span,
+                    )
+                    .with_help(String::from("end the previous macro with `.ENDM`")));
+                }
+                _ => body.push(self.advance()),
+            }
+        }
+    }
+
+    // Replace the invocation by the body of the macro, with the parameters replaced by
+    // the arguments and local `@labels` renamed so that each expansion has its own
+    fn expand_macro(
+        &mut self,
+        name: &str,
+        span: Span,
+        operands: Vec<Token>,
+    ) -> Result<(), Diagnostic> {
+        if self.expanding.iter().any(|expanding| expanding == name) {
+            return Err(
+                Diagnostic::new(format!("Macro `{}` expands itself", name), span)
+                    .with_help(format!("{} -> {}", self.expanding.join(" -> "), name)),
+            );
+        }
+        let arguments = split_arguments(&operands)?;
+        let definition = &self.macros[name];
+        if arguments.len() != definition.params.len() {
+            let span = match (operands.first(), operands.last()) {
+                (Some(first), Some(last)) => first.span.to(last.span),
+                _ => span,
+            };
+            return Err(Diagnostic::new(
+                format!(
+                    "`{}` takes {} argument{}, not {}",
+                    name,
+                    definition.params.len(),
+                    if definition.params.len() == 1 {
+                        ""
+                    } else {
+                        "s"
+                    },
+                    arguments.len()
+                ),
+                span,
+            )
+            .with_help(format!(
+                "`.MACRO {} {}`",
+                name,
+                definition.params.join(", ")
+            )));
+        }
+
+        self.expansions += 1;
+        let mut expanded = Vec::new();
+        for token in definition.body.iter() {
+            match &token.token_type {
+                TokenType::Label(label) => {
+                    if let Some(i) = definition.params.iter().position(|param| param == label) {
+                        expanded.extend(arguments[i].iter().cloned());
+                    } else if label.starts_with('@') {
+                        expanded.push(Token {
+                            token_type: TokenType::Label(format!("{}#{}", label, self.expansions)),
+                            span: token.span,
+                        });
+                    } else {
+                        expanded.push(token.clone());
+                    }
+                }
+                _ => expanded.push(token.clone()),
+            }
+        }
+        for token_type in [TokenType::Newline, TokenType::MacroEnd] {
+            expanded.push(Token { token_type, span });
+        }
+        self.expanding.push(name.to_string());
+        self.tokens.splice(self.current..self.current, expanded);
+        Ok(())
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Keyword(token_type) => write!(f, "{}", token_type),
+            Operand::Value(_) => write!(f, "value"),
+            Operand::LongValue(_) => write!(f, "LONG value"),
+        }
+    }
+}
+
+fn is_keyword(token_type: &TokenType) -> bool {
+    matches!(
+        token_type,
+        TokenType::RegV(_)
+            | TokenType::RegI
+            | TokenType::IndirectI
+            | TokenType::DelayTimer
+            | TokenType::SoundTimer
+            | TokenType::Key
+            | TokenType::Sprite
+            | TokenType::BigSprite
+            | TokenType::Bcd
+            | TokenType::Flags
+    )
+}
+
+// Operands are separated by commas. Keywords stand alone,
+// so `DRW V1 V2 5` without commas still works.
+fn split_operands(tokens: &[Token]) -> Result<Vec<(Operand, Span)>, Diagnostic> {
+    let mut groups: Vec<&[Token]> = Vec::new();
+    let mut start = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        if token.token_type == TokenType::Comma || is_keyword(&token.token_type) {
+            if start < i {
+                groups.push(&tokens[start..i]);
+            } else if token.token_type == TokenType::Comma
+                && (i == 0 || tokens[i - 1].token_type == TokenType::Comma)
+            {
+                return Err(Diagnostic::new(
+                    String::from("Expected an operand before `,`"),
+                    token.span,
+                ));
+            }
+            if token.token_type != TokenType::Comma {
+                groups.push(&tokens[i..=i]);
+            }
+            start = i + 1;
+        }
+    }
+    match tokens.last() {
+        Some(last) if last.token_type == TokenType::Comma => {
+            return Err(Diagnostic::new(
+                String::from("Expected an operand after `,`"),
+                last.span,
+            ))
+        }
+        _ => {}
+    }
+    if start < tokens.len() {
+        groups.push(&tokens[start..]);
+    }
+
+    groups
+        .into_iter()
+        .map(|group| {
+            let span = group[0].span.to(group[group.len() - 1].span);
+            let operand =
+                match &group[0].token_type {
+                    keyword if is_keyword(keyword) => Operand::Keyword(keyword.clone()),
+                    TokenType::Long => Operand::LongValue(expression::parse(&group[1..]).map_err(
+                        |diagnostic| match group.len() {
+                            1 => Diagnostic::new(
+                                String::from("Expected an address after `LONG`"),
+                                span,
+                            ),
+                            _ => diagnostic,
+                        },
+                    )?),
+                    _ => Operand::Value(expression::parse(group)?),
+                };
+            Ok((operand, span))
+        })
+        .collect()
+}
+
+// The file name of .INCLUDE and .INCBIN
+fn path_operand(operands: &[Token], missing: Diagnostic) -> Result<(String, Span), Diagnostic> {
+    match operands {
+        [Token {
+            token_type: TokenType::Str(name),
+            span,
+        }] => Ok((name.clone(), *span)),
+        [] => Err(missing),
+        [token, ..] => Err(unexpected(
+            &token.token_type,
+            "a file name in quotes",
+            token.span,
+        )),
+    }
+}
+
+// `.MACRO NAME param, param`
+fn macro_header(
+    operands: &[Token],
+    missing: Diagnostic,
+) -> Result<(String, Span, Vec<String>), Diagnostic> {
+    let mut names = operands
+        .iter()
+        .filter(|token| token.token_type != TokenType::Comma);
+    let (name, name_span) = match names.next() {
+        Some(Token {
+            token_type: TokenType::Label(name),
+            span,
+        }) => (name.clone(), *span),
+        Some(token) => return Err(unexpected(&token.token_type, "a macro name", token.span)),
+        None => return Err(missing),
+    };
+    let mut params = Vec::new();
+    for token in names {
+        match &token.token_type {
+            TokenType::Label(param) if params.contains(param) => {
+                return Err(Diagnostic::new(
+                    format!("Parameter `{}` is already defined", param),
+                    token.span,
+                ))
+            }
+            TokenType::Label(param) => params.push(param.clone()),
+            other => return Err(unexpected(other, "a parameter name", token.span)),
+        }
+    }
+    Ok((name, name_span, params))
+}
+
+// The arguments of a macro invocation, separated by the commas outside parentheses
+fn split_arguments(tokens: &[Token]) -> Result<Vec<Vec<Token>>, Diagnostic> {
+    let mut arguments = Vec::new();
+    let mut argument = Vec::new();
+    let mut depth = 0;
+    for token in tokens {
+        match token.token_type {
+            TokenType::Comma if depth == 0 => {
+                if argument.is_empty() {
+                    return Err(Diagnostic::new(
+                        String::from("Expected an argument before `,`"),
+                        token.span,
+                    ));
+                }
+                arguments.push(std::mem::take(&mut argument));
+                continue;
+            }
+            TokenType::LeftParen => depth += 1,
+            TokenType::RightParen => depth -= 1,
+            _ => {}
+        }
+        argument.push(token.clone());
+    }
+    match (argument.is_empty(), tokens.last()) {
+        (false, _) => arguments.push(argument),
+        (true, Some(last)) => {
+            return Err(Diagnostic::new(
+                String::from("Expected an argument after `,`"),
+                last.span,
+            ))
+        }
+        (true, None) => {}
+    }
+    Ok(arguments)
+}
+
+fn parse_instruction(mnemonic: &TokenType, operands: &[Operand]) -> Option<Opcode> {
+    use Operand::*;
+    use TokenType::*;
+
+    let opcode = match (mnemonic, operands) {
+        (Clear, []) => Opcode::Cls,
+        (Ret, []) => Opcode::Ret,
+        (ScrollDown, [Value(n)]) => Opcode::ScrollDown(n.clone()),
+        (ScrollUp, [Value(n)]) => Opcode::ScrollUp(n.clone()),
+        (ScrollRight, []) => Opcode::ScrollRight,
+        (ScrollLeft, []) => Opcode::ScrollLeft,
+        (Exit, []) => Opcode::Exit,
+        (Low, []) => Opcode::Low,
+        (High, []) => Opcode::High,
+        (Jump, [Keyword(RegV(0)), Value(target)]) => Opcode::JumpV0(target.clone()),
+        (Jump, [Value(target)]) => Opcode::Jump(target.clone()),
+        (Call, [Value(target)]) => Opcode::Call(target.clone()),
+        (SkipEq, [Keyword(RegV(x)), Keyword(RegV(y))]) => Opcode::SkipEqReg(*x, *y),
+        (SkipEq, [Keyword(RegV(x)), Value(kk)]) => Opcode::SkipEqByte(*x, kk.clone()),
+        (SkipNotEq, [Keyword(RegV(x)), Keyword(RegV(y))]) => Opcode::SkipNotEqReg(*x, *y),
+        (SkipNotEq, [Keyword(RegV(x)), Value(kk)]) => Opcode::SkipNotEqByte(*x, kk.clone()),
+        (SaveRange, [Keyword(RegV(x)), Keyword(RegV(y))]) => Opcode::SaveRange(*x, *y),
+        (LoadRange, [Keyword(RegV(x)), Keyword(RegV(y))]) => Opcode::LoadRange(*x, *y),
+        (Load, [Keyword(RegV(x)), Keyword(RegV(y))]) => Opcode::LoadReg(*x, *y),
+        (Load, [Keyword(RegV(x)), Value(kk)]) => Opcode::LoadByte(*x, kk.clone()),
+        (Load, [Keyword(RegV(x)), Keyword(DelayTimer)]) => Opcode::LoadDelay(*x),
+        (Load, [Keyword(RegV(x)), Keyword(Key)]) => Opcode::WaitKey(*x),
+        (Load, [Keyword(RegV(x)), Keyword(RegI | IndirectI)]) => Opcode::Restore(*x),
+        (Load, [Keyword(RegV(x)), Keyword(Flags)]) => Opcode::RestoreFlags(*x),
+        (Load, [Keyword(RegI), LongValue(target)]) => Opcode::LoadLong(target.clone()),
+        (Load, [Keyword(RegI | IndirectI), Keyword(RegV(x))]) => Opcode::Store(*x),
+        (Load, [Keyword(RegI), Value(target)]) => Opcode::LoadI(target.clone()),
+        (Load, [Keyword(DelayTimer), Keyword(RegV(x))]) => Opcode::SetDelay(*x),
+        (Load, [Keyword(SoundTimer), Keyword(RegV(x))]) => Opcode::SetSound(*x),
+        (Load, [Keyword(Sprite), Keyword(RegV(x))]) => Opcode::LoadSprite(*x),
+        (Load, [Keyword(BigSprite), Keyword(RegV(x))]) => Opcode::LoadBigSprite(*x),
+        (Load, [Keyword(Bcd), Keyword(RegV(x))]) => Opcode::Bcd(*x),
+        (Load, [Keyword(Flags), Keyword(RegV(x))]) => Opcode::StoreFlags(*x),
+        (Add, [Keyword(RegV(x)), Keyword(RegV(y))]) => Opcode::AddReg(*x, *y),
+        (Add, [Keyword(RegV(x)), Value(kk)]) => Opcode::AddByte(*x, kk.clone()),
+        (Add, [Keyword(RegI), Keyword(RegV(x))]) => Opcode::AddI(*x),
+        (Or, [Keyword(RegV(x)), Keyword(RegV(y))]) => Opcode::Or(*x, *y),
+        (And, [Keyword(RegV(x)), Keyword(RegV(y))]) => Opcode::And(*x, *y),
+        (Xor, [Keyword(RegV(x)), Keyword(RegV(y))]) => Opcode::Xor(*x, *y),
+        (Sub, [Keyword(RegV(x)), Keyword(RegV(y))]) => Opcode::Sub(*x, *y),
+        (SubNotBorrow, [Keyword(RegV(x)), Keyword(RegV(y))]) => Opcode::SubNotBorrow(*x, *y),
+        // Without Vy, shift Vx in place whatever the shift quirk
+        (ShiftRight, [Keyword(RegV(x))]) => Opcode::ShiftRight(*x, *x),
+        (ShiftRight, [Keyword(RegV(x)), Keyword(RegV(y))]) => Opcode::ShiftRight(*x, *y),
+        (ShiftLeft, [Keyword(RegV(x))]) => Opcode::ShiftLeft(*x, *x),
+        (ShiftLeft, [Keyword(RegV(x)), Keyword(RegV(y))]) => Opcode::ShiftLeft(*x, *y),
+        (Random, [Keyword(RegV(x)), Value(kk)]) => Opcode::Random(*x, kk.clone()),
+        (Draw, [Keyword(RegV(x)), Keyword(RegV(y)), Value(n)]) => Opcode::Draw(*x, *y, n.clone()),
+        (SkipPress, [Keyword(RegV(x))]) => Opcode::SkipPress(*x),
+        (SkipNotPress, [Keyword(RegV(x))]) => Opcode::SkipNotPress(*x),
+        (Plane, [Value(n)]) => Opcode::Plane(n.clone()),
+        (Audio, []) => Opcode::Audio,
+        (Pitch, [Keyword(RegV(x))]) => Opcode::Pitch(*x),
+        _ => return None,
+    };
+    Some(opcode)
+}
+
+fn invalid_operands(mnemonic: &Token, missing: bool, span: Span) -> Diagnostic {
+    let name = mnemonic.token_type.to_string();
+    let forms = match FORMS.iter().find(|(form, _)| *form == name) {
+        Some((_, forms)) => forms,
+        None if mnemonic.token_type == TokenType::Assign => {
+            return Diagnostic::new(String::from("Expected a label before `:`"), mnemonic.span)
+        }
+        None => {
+            return Diagnostic::new(
+                format!("Expected an instruction, found `{}`", name),
+                mnemonic.span,
+            )
+        }
+    };
+    let message = if missing {
+        format!("Missing operands for `{}`", name)
+    } else {
+        format!("Invalid operands for `{}`", name)
+    };
+    let expected: Vec<String> = forms.iter().map(|form| format!("`{}`", form)).collect();
+    Diagnostic::new(message, span).with_help(format!("expected {}", expected.join(" or ")))
+}
+
+// `#` for a lit pixel and `.` for an unlit one, up to 16 pixels padded to whole bytes
+fn sprite_row(row: &str, span: Span) -> Result<Vec<u8>, Diagnostic> {
+    if row.is_empty() || row.len() > 16 {
+        return Err(Diagnostic::new(
+            format!("Sprite rows are 1 to 16 pixels wide, not {}", row.len()),
+            span,
+        ));
+    }
+    let mut bits: u16 = 0;
+    for (i, pixel) in row.chars().enumerate() {
+        match pixel {
+            '#' => bits |= 0x8000 >> i,
+            '.' => {}
+            other => {
+                return Err(Diagnostic::new(
+                    format!("Unexpected `{}` in a sprite row", other),
+                    span,
+                )
+                .with_help(String::from("rows are made of `#` for lit pixels and `.`")))
+            }
+        }
+    }
+    let bytes = bits.to_be_bytes();
+    Ok(bytes[..row.len().div_ceil(8)].to_vec())
+}
+
+fn unexpected(found: &TokenType, expected: &str, span: Span) -> Diagnostic {
+    Diagnostic::new(format!("Expected {}, found `{}`", expected, found), span)
+}
@@ -0,0 +1,83 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::diagnostic::{Diagnostic, Span};
+
+#[derive(Debug)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub text: String,
+    // The `.INCLUDE` which brought the file in, None for the main file
+    pub included_from: Option<Span>,
+}
+
+// Every file read while assembling, which spans refer to by index
+#[derive(Debug, Default)]
+pub struct Sources {
+    files: Vec<SourceFile>,
+}
+
+impl Sources {
+    pub fn add(&mut self, path: PathBuf, text: String, included_from: Option<Span>) -> usize {
+        self.files.push(SourceFile {
+            path,
+            text,
+            included_from,
+        });
+        self.files.len() - 1
+    }
+
+    pub fn get(&self, file: usize) -> &SourceFile {
+        &self.files[file]
+    }
+
+    // `file` and the files which included it, innermost first
+    pub fn chain(&self, file: usize) -> impl Iterator<Item = &SourceFile> {
+        let mut next = Some(file);
+        std::iter::from_fn(move || {
+            let file = self.get(next?);
+            next = file.included_from.map(|span| span.file);
+            Some(file)
+        })
+    }
+
+    // Where `span` is, as `file:line`
+    pub fn location(&self, span: Span) -> String {
+        format!("{}:{}", self.get(span.file).path.display(), span.line)
+    }
+
+    // A diagnostic with its source excerpt, followed by the chain of includes
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let file = self.get(diagnostic.span.file);
+        let mut text = diagnostic.render(&file.path.display().to_string(), &file.text);
+        for file in self.chain(diagnostic.span.file) {
+            if let Some(span) = file.included_from {
+                text.push_str(&format!("\nnote: included from {}", self.location(span)));
+            }
+        }
+        text
+    }
+}
+
+// Directory the paths in a file are relative to
+pub fn directory(path: &Path) -> &Path {
+    path.parent().unwrap_or_else(|| Path::new(""))
+}
+
+// Every error of a failed assembly, with the sources to show them
+#[derive(Debug)]
+pub struct AssembleErrors {
+    pub diagnostics: Vec<Diagnostic>,
+    pub sources: Sources,
+}
+
+impl fmt::Display for AssembleErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rendered: Vec<String> = self
+            .diagnostics
+            .iter()
+            .map(|diagnostic| self.sources.render(diagnostic))
+            .collect();
+        write!(f, "{}", rendered.join("\n\n"))
+    }
+}
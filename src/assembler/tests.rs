@@ -0,0 +1,463 @@
+use std::path::Path;
+
+use super::{assemble, Diagnostic};
+
+fn bytes(source: &str) -> Vec<u8> {
+    match assemble(Path::new("test.chasm"), source) {
+        Ok(bytes) => bytes,
+        Err(errors) => panic!("unexpected errors:\n{}", errors),
+    }
+}
+
+fn errors(source: &str) -> Vec<Diagnostic> {
+    match assemble(Path::new("test.chasm"), source) {
+        Ok(bytes) => panic!("expected errors, assembled to {:02X?}", bytes),
+        Err(errors) => errors.diagnostics,
+    }
+}
+
+fn messages(source: &str) -> Vec<String> {
+    errors(source)
+        .into_iter()
+        .map(|diagnostic| diagnostic.message)
+        .collect()
+}
+
+#[test]
+fn fills_memory_up_to_0xffff() {
+    let program = bytes(".ORIG 0xFFFE\nCLS\n");
+    assert_eq!(program.len(), 0x10000 - 0x200);
+    assert_eq!(program[program.len() - 2..], [0x00, 0xE0]);
+}
+
+#[test]
+fn reports_code_past_the_end_of_memory() {
+    assert_eq!(
+        messages(".ORIG 0xFFFE\nCLS\nCLS\n"),
+        ["0x10001 is past the end of memory"]
+    );
+    assert_eq!(
+        messages(".ORIG 0xFFFE\nCLS\nend:\n.DB 1\n"),
+        ["0x10000 is past the end of memory"]
+    );
+}
+
+#[test]
+fn labels_may_follow_the_last_byte() {
+    let errors = errors(".ORIG 0xFFFE\nCLS\nend:\nJP end\n");
+    assert_eq!(errors[0].message, "0x10001 is past the end of memory");
+    let program = bytes(".ORIG 0xFFFC\n.DW end - 2\nCLS\nend:\n");
+    assert_eq!(program[program.len() - 4..], [0xFF, 0xFE, 0x00, 0xE0]);
+}
+
+#[test]
+fn reassembles_every_disassembled_word() {
+    use crate::disassembler::Disassembler;
+
+    for opcode in 0..=0xFFFF_u16 {
+        let source = Disassembler::decode_op(opcode);
+        let assembled = match assemble(Path::new("test.chasm"), &source) {
+            Ok(bytes) => bytes,
+            Err(errors) => panic!(
+                "`{}` ({:#06x}) doesn't assemble:\n{}",
+                source, opcode, errors
+            ),
+        };
+        assert_eq!(assembled, opcode.to_be_bytes(), "`{}`", source);
+    }
+}
+
+#[test]
+fn orig_needs_its_constants_defined_before() {
+    let program = bytes(".EQU BASE, 0x204\n.ORIG BASE\nCLS\n");
+    assert_eq!(program, [0, 0, 0, 0, 0x00, 0xE0]);
+    let errors = errors(".ORIG BASE\n.EQU BASE, 0x300\n");
+    assert_eq!(errors[0].message, "Undefined name `BASE`");
+    assert_eq!(
+        errors[0].help.as_deref(),
+        Some("the labels and constants used here must be defined before this line")
+    );
+}
+
+#[test]
+fn resolves_labels_before_and_after_their_definition() {
+    let source = "start:\n    CALL update\n    JP start\nupdate:\n    RET\n";
+    assert_eq!(bytes(source), [0x22, 0x04, 0x12, 0x00, 0x00, 0xEE]);
+    assert_eq!(bytes("loop: JP loop\n"), [0x12, 0x00]);
+}
+
+#[test]
+fn encodes_every_instruction_form() {
+    let forms: [(&str, &[u8]); 52] = [
+        ("CLS", &[0x00, 0xE0]),
+        ("RET", &[0x00, 0xEE]),
+        ("SCD 3", &[0x00, 0xC3]),
+        ("SCU 4", &[0x00, 0xD4]),
+        ("SCR", &[0x00, 0xFB]),
+        ("SCL", &[0x00, 0xFC]),
+        ("EXIT", &[0x00, 0xFD]),
+        ("LOW", &[0x00, 0xFE]),
+        ("HIGH", &[0x00, 0xFF]),
+        ("JP 0x345", &[0x13, 0x45]),
+        ("CALL 0x345", &[0x23, 0x45]),
+        ("SE V1, 0x22", &[0x31, 0x22]),
+        ("SNE V1, 0x22", &[0x41, 0x22]),
+        ("SE V1, V2", &[0x51, 0x20]),
+        ("SAVE V1, V2", &[0x51, 0x22]),
+        ("LOAD V1, V2", &[0x51, 0x23]),
+        ("LD V1, 0x22", &[0x61, 0x22]),
+        ("ADD V1, 0x22", &[0x71, 0x22]),
+        ("LD V1, V2", &[0x81, 0x20]),
+        ("OR V1, V2", &[0x81, 0x21]),
+        ("AND V1, V2", &[0x81, 0x22]),
+        ("XOR V1, V2", &[0x81, 0x23]),
+        ("ADD V1, V2", &[0x81, 0x24]),
+        ("SUB V1, V2", &[0x81, 0x25]),
+        ("SHR V1", &[0x81, 0x16]),
+        ("SHR V1, V2", &[0x81, 0x26]),
+        ("SUBN V1, V2", &[0x81, 0x27]),
+        ("SHL V1", &[0x81, 0x1E]),
+        ("SHL V1, V2", &[0x81, 0x2E]),
+        ("SNE V1, V2", &[0x91, 0x20]),
+        ("LD I, 0x345", &[0xA3, 0x45]),
+        ("LD I, LONG 0x4567", &[0xF0, 0x00, 0x45, 0x67]),
+        ("JP V0, 0x345", &[0xB3, 0x45]),
+        ("RND V1, 0x22", &[0xC1, 0x22]),
+        ("DRW V1, V2, 5", &[0xD1, 0x25]),
+        ("SKP V1", &[0xE1, 0x9E]),
+        ("SKNP V1", &[0xE1, 0xA1]),
+        ("PLANE 3", &[0xF3, 0x01]),
+        ("AUDIO", &[0xF0, 0x02]),
+        ("LD V1, DT", &[0xF1, 0x07]),
+        ("LD V1, K", &[0xF1, 0x0A]),
+        ("LD DT, V1", &[0xF1, 0x15]),
+        ("LD ST, V1", &[0xF1, 0x18]),
+        ("ADD I, V1", &[0xF1, 0x1E]),
+        ("LD F, V1", &[0xF1, 0x29]),
+        ("LD HF, V1", &[0xF1, 0x30]),
+        ("LD B, V1", &[0xF1, 0x33]),
+        ("PITCH V1", &[0xF1, 0x3A]),
+        ("LD [I], V1", &[0xF1, 0x55]),
+        ("LD V1, [I]", &[0xF1, 0x65]),
+        ("LD R, V1", &[0xF1, 0x75]),
+        ("LD V1, R", &[0xF1, 0x85]),
+    ];
+    for (source, expected) in forms.iter() {
+        assert_eq!(bytes(source), *expected, "`{}`", source);
+        assert_eq!(bytes(&source.to_lowercase()), *expected, "`{}`", source);
+    }
+}
+
+#[test]
+fn round_trips_disassembled_roms() {
+    use crate::disassembler::Disassembler;
+
+    // A loop with data after it, which the disassembler writes as raw words
+    let rom = [
+        0x60, 0x05, 0xA2, 0x0A, 0xD0, 0x05, 0x70, 0x01, 0x12, 0x04, 0xF0, 0x90, 0xF0, 0x90, 0xF0,
+        0x00,
+    ];
+    let source: Vec<String> = rom
+        .chunks(2)
+        .map(|word| Disassembler::decode_op(u16::from_be_bytes([word[0], word[1]])))
+        .collect();
+    assert_eq!(bytes(&source.join("\n")), rom);
+}
+
+#[test]
+fn reports_every_error_in_order() {
+    let errors = errors("LD V16, 1\nCLS\nJMP 0x300\nLD V1, missing\n");
+    let found: Vec<_> = errors
+        .iter()
+        .map(|error| (error.span.line, error.span.column, error.message.as_str()))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            (1, 4, "Invalid register `V16`"),
+            (3, 1, "Unknown instruction `JMP`"),
+            (4, 8, "Undefined name `missing`"),
+        ]
+    );
+    assert_eq!(errors[1].help.as_deref(), Some("did you mean `JP`?"));
+}
+
+#[test]
+fn renders_the_source_line_under_the_error() {
+    let errors = assemble(Path::new("game.chasm"), "CLS\n\tLD V16, 1\n").unwrap_err();
+    assert_eq!(
+        errors.to_string(),
+        "error: Invalid register `V16`\n \
+         --> game.chasm:2:5\n  \
+         |\n\
+         2 | \tLD V16, 1\n  \
+         | \t   ^^^\n  \
+         = help: `V16` is not a register, did you mean `VF`? Registers go from V0 to VF"
+    );
+}
+
+#[test]
+fn suggests_operand_forms() {
+    let errors = errors("LD DT, 5\nDRW V1, V2\n");
+    assert_eq!(errors.len(), 2);
+    for error in errors.iter() {
+        assert!(error.help.is_some(), "{:?}", error);
+    }
+}
+
+#[test]
+fn reports_labels_defined_twice() {
+    let errors = errors("start:\nCLS\nstart:\nRET\n");
+    assert_eq!(errors[0].message, "`start` is already defined");
+    assert_eq!(errors[0].span.line, 3);
+    assert_eq!(
+        errors[0].help.as_deref(),
+        Some("first defined at test.chasm:1")
+    );
+}
+
+#[test]
+fn assembles_data_directives() {
+    assert_eq!(bytes(".DB 1, 0xFF, -1\n"), [0x01, 0xFF, 0xFF]);
+    assert_eq!(
+        bytes("start:\n.DW 0x1234, start, -2\n"),
+        [0x12, 0x34, 0x02, 0x00, 0xFF, 0xFE]
+    );
+    assert_eq!(bytes(".TEXT \"HI\\n\\\"\"\n"), b"HI\n\"");
+    assert_eq!(bytes(".SPRITE \"#..##..#\", \".##..##.\"\n"), [0x99, 0x66]);
+    assert_eq!(
+        bytes(".SPRITE \"#..............#\", \"..#.............\"\n"),
+        [0x80, 0x01, 0x20, 0x00]
+    );
+}
+
+#[test]
+fn aligns_and_places_code() {
+    assert_eq!(bytes(".DB 1\n.ALIGN\nCLS\n"), [0x01, 0x00, 0x00, 0xE0]);
+    assert_eq!(bytes(".DB 1\n.ALIGN 4\n.DB 2\n"), [0x01, 0, 0, 0, 0x02]);
+    assert_eq!(bytes(".ALIGN 4\nCLS\n"), [0x00, 0xE0]);
+    assert_eq!(
+        bytes(".ORIG 0x204\nend:\nJP end\n.ORIG\nCLS\n"),
+        [0x00, 0xE0, 0x00, 0x00, 0x12, 0x04]
+    );
+}
+
+#[test]
+fn reports_invalid_placement() {
+    assert_eq!(
+        messages(".ALIGN 3\n.ORIG 0x100\n"),
+        [
+            "Expected a power of two, found 3",
+            "Address 0x100 is outside the program"
+        ]
+    );
+    let errors = errors("CLS\nRET\n.ORIG 0x202\nCLS\n");
+    assert_eq!(
+        errors[0].message,
+        "Overlaps code already assembled at 0x202"
+    );
+    assert_eq!(errors[0].span.line, 4);
+}
+
+#[test]
+fn reports_invalid_data() {
+    assert_eq!(
+        messages(".DB 256\n.SPRITE \"#x\"\n.TEXT 5\n.UNKNOWN\n").len(),
+        4
+    );
+}
+
+#[test]
+fn reads_every_number_format() {
+    assert_eq!(
+        bytes(".DB 10, 0x1F, 0X1f, #20, $21, 0b101\n"),
+        [10, 0x1F, 0x1F, 0x20, 0x21, 0b101]
+    );
+}
+
+#[test]
+fn checks_values_against_their_field() {
+    assert_eq!(bytes("LD V1, -128\nLD V1, 255\n"), [0x61, 0x80, 0x61, 0xFF]);
+    assert_eq!(
+        messages("LD V1, 256\nDRW V1, V2, 16\nJP 0x1000\nLD V1, 0x10000\n"),
+        [
+            "Value 256 doesn't fit in a byte",
+            "Value 16 doesn't fit in a nibble",
+            "Value 4096 doesn't fit in an address",
+            "Invalid number `0x10000`",
+        ]
+    );
+    let errors = errors("LD I, 0x1000\n");
+    assert_eq!(
+        errors[0].help.as_deref(),
+        Some("use `LD I, LONG` for addresses above 0xFFF")
+    );
+}
+
+#[test]
+fn evaluates_expressions() {
+    let source = ".EQU WIDTH, 64\n\
+                  LD V1, WIDTH / 2 - 4\n\
+                  LD V2, 1 + 2 * 3\n\
+                  LD V3, (1 + 2) * 3\n\
+                  LD V4, 1 << 4 | 3 & ~1\n\
+                  LD V5, hi(table)\n\
+                  LD V6, lo(table) + 1\n\
+                  table:\n";
+    assert_eq!(
+        bytes(source),
+        [0x61, 28, 0x62, 7, 0x63, 9, 0x64, 0x12, 0x65, 0x02, 0x66, 0x0D]
+    );
+    assert_eq!(
+        messages("LD V1, 1 / 0\nLD V1, unknown(2)\n"),
+        ["Division by zero", "Unknown function `unknown`"]
+    );
+}
+
+#[test]
+fn uses_constants_and_aliases_before_their_definition() {
+    assert_eq!(bytes("LD V1, SPEED\n.EQU SPEED, 3\n"), [0x61, 3]);
+    assert_eq!(
+        bytes(".DEFINE ball V3\n.DEFINE step 1 + 1\nADD ball, step * 2\n"),
+        [0x73, 4]
+    );
+}
+
+#[test]
+fn reports_constant_cycles() {
+    let errors = errors(".EQU A, C + 1\n.EQU C, A * 2\nLD V1, A\n");
+    assert_eq!(
+        errors[0].message,
+        "Constant `A` is defined in terms of itself"
+    );
+    assert_eq!(errors[0].help.as_deref(), Some("A -> C -> A"));
+}
+
+#[test]
+fn gives_each_macro_expansion_its_own_local_labels() {
+    let source = ".MACRO WAIT_KEY reg, key\n\
+                  @wait:\n\
+                  LD reg, K\n\
+                  SNE reg, key\n\
+                  JP @wait\n\
+                  .ENDM\n\
+                  WAIT_KEY V1, 0xA\n\
+                  WAIT_KEY V2, (1 + 2)\n";
+    assert_eq!(
+        bytes(source),
+        [
+            0xF1, 0x0A, 0x41, 0x0A, 0x12, 0x00, //
+            0xF2, 0x0A, 0x42, 0x03, 0x12, 0x06,
+        ]
+    );
+}
+
+#[test]
+fn reports_invalid_macro_uses() {
+    let source = ".MACRO TWO first, second\n\
+                  LD first, second\n\
+                  .ENDM\n\
+                  .MACRO SELF\n\
+                  SELF\n\
+                  .ENDM\n\
+                  TWO V1\n\
+                  TWO V1,\n\
+                  SELF\n\
+                  .ENDM\n";
+    assert_eq!(
+        messages(source),
+        [
+            "Macro `SELF` expands itself",
+            "`TWO` takes 2 arguments, not 1",
+            "Expected an argument after `,`",
+            "`.ENDM` without `.MACRO`",
+        ]
+    );
+    assert_eq!(messages(".MACRO OPEN\nCLS\n"), ["Missing `.ENDM`"]);
+}
+
+// A directory of source files, removed when dropped
+struct Files(std::path::PathBuf);
+
+impl Files {
+    fn new(name: &str, files: &[(&str, &[u8])]) -> Self {
+        let dir = std::env::temp_dir().join(format!("chiprs-{}-{}", name, std::process::id()));
+        for (path, contents) in files.iter() {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        Files(dir)
+    }
+
+    fn assemble(&self, path: &str) -> Result<Vec<u8>, super::AssembleErrors> {
+        let path = self.0.join(path);
+        let source = std::fs::read_to_string(&path).unwrap();
+        assemble(&path, &source)
+    }
+}
+
+impl Drop for Files {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn includes_files_relative_to_the_including_file() {
+    let files = Files::new(
+        "include",
+        &[
+            ("main.chasm", b".INCLUDE \"lib/util.chasm\"\nCALL clear\n"),
+            (
+                "lib/util.chasm",
+                b".INCLUDE \"sprites.chasm\"\nclear:\nCLS\nRET\n",
+            ),
+            ("lib/sprites.chasm", b"JP 0x206\n.INCBIN \"ball.bin\"\n"),
+            ("lib/ball.bin", &[0x80, 0x40]),
+        ],
+    );
+    assert_eq!(
+        files.assemble("main.chasm").unwrap(),
+        [0x12, 0x06, 0x80, 0x40, 0x00, 0xE0, 0x00, 0xEE, 0x22, 0x04]
+    );
+}
+
+#[test]
+fn reports_include_cycles_and_their_chain() {
+    let files = Files::new(
+        "include-cycle",
+        &[
+            ("a.chasm", b".INCLUDE \"b.chasm\"\n"),
+            ("b.chasm", b"CLS\n.INCLUDE \"./a.chasm\"\n"),
+        ],
+    );
+    let errors = files.assemble("a.chasm").unwrap_err();
+    let error = &errors.diagnostics[0];
+    assert_eq!(error.message, "`./a.chasm` includes itself");
+    assert_eq!(error.span.line, 2);
+    let chain = error.help.as_ref().unwrap();
+    assert!(
+        chain.starts_with(&files.0.join("a.chasm").display().to_string()),
+        "{}",
+        chain
+    );
+    assert!(errors.to_string().contains(&format!(
+        "note: included from {}:1",
+        files.0.join("a.chasm").display()
+    )));
+}
+
+#[test]
+fn reports_missing_files() {
+    let files = Files::new(
+        "include-missing",
+        &[(
+            "main.chasm",
+            b".INCLUDE \"nothing.chasm\"\n.INCBIN \"nothing.bin\"\n",
+        )],
+    );
+    let errors = files.assemble("main.chasm").unwrap_err();
+    assert_eq!(errors.diagnostics.len(), 2);
+    assert!(errors.diagnostics[0].message.starts_with("Unable to read"));
+}
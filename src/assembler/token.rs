@@ -1,7 +1,11 @@
+use std::fmt;
+
+use super::diagnostic::{Diagnostic, Span};
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
-    pub line: usize,
+    pub span: Span,
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -10,8 +14,25 @@ pub enum TokenType {
     Number(u16),
     Label(String),
     Directive(String),
-    Error(String),
+    Str(String),
+    // Reported by the parser, which skips the rest of the line
+    Error(Diagnostic),
     Assign,
+    Comma,
+    // Expressions
+    LeftParen,
+    RightParen,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
+    Shl,
+    Shr,
     Clear,
     Ret,
     Jump,
@@ -31,11 +52,110 @@ pub enum TokenType {
     ShiftLeft,
     Random,
     Draw,
+    // SUPER-CHIP
+    ScrollDown,
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    Low,
+    High,
+    // XO-CHIP
+    ScrollUp,
+    SaveRange,
+    LoadRange,
+    Plane,
+    Audio,
+    Pitch,
     DelayTimer,
     SoundTimer,
     Key,
     RegI,
+    // [I], the memory pointed to by I
+    IndirectI,
     Sprite,
+    BigSprite,
     Bcd,
+    // RPL user flags
+    Flags,
+    // LD I, LONG nnnn
+    Long,
+    // Statements end at the end of a line
+    Newline,
+    // Follows the tokens of a macro expansion, never written in the source
+    MacroEnd,
     Eof,
 }
+
+// Operands and mnemonics as written in the source, for error messages
+impl fmt::Display for TokenType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            TokenType::RegV(x) => return write!(f, "V{}", x),
+            TokenType::Number(n) => return write!(f, "{}", n),
+            TokenType::Label(name) => return write!(f, "{}", name),
+            TokenType::Directive(name) => return write!(f, ".{}", name),
+            TokenType::Str(text) => return write!(f, "{:?}", text),
+            TokenType::Error(_) => "invalid token",
+            TokenType::Assign => ":",
+            TokenType::Comma => ",",
+            TokenType::LeftParen => "(",
+            TokenType::RightParen => ")",
+            TokenType::Plus => "+",
+            TokenType::Minus => "-",
+            TokenType::Star => "*",
+            TokenType::Slash => "/",
+            TokenType::Percent => "%",
+            TokenType::Ampersand => "&",
+            TokenType::Pipe => "|",
+            TokenType::Caret => "^",
+            TokenType::Tilde => "~",
+            TokenType::Shl => "<<",
+            TokenType::Shr => ">>",
+            TokenType::Clear => "CLS",
+            TokenType::Ret => "RET",
+            TokenType::Jump => "JP",
+            TokenType::Call => "CALL",
+            TokenType::Load => "LD",
+            TokenType::SkipEq => "SE",
+            TokenType::SkipNotEq => "SNE",
+            TokenType::SkipPress => "SKP",
+            TokenType::SkipNotPress => "SKNP",
+            TokenType::Add => "ADD",
+            TokenType::Or => "OR",
+            TokenType::And => "AND",
+            TokenType::Xor => "XOR",
+            TokenType::Sub => "SUB",
+            TokenType::ShiftRight => "SHR",
+            TokenType::SubNotBorrow => "SUBN",
+            TokenType::ShiftLeft => "SHL",
+            TokenType::Random => "RND",
+            TokenType::Draw => "DRW",
+            TokenType::ScrollDown => "SCD",
+            TokenType::ScrollRight => "SCR",
+            TokenType::ScrollLeft => "SCL",
+            TokenType::Exit => "EXIT",
+            TokenType::Low => "LOW",
+            TokenType::High => "HIGH",
+            TokenType::ScrollUp => "SCU",
+            TokenType::SaveRange => "SAVE",
+            TokenType::LoadRange => "LOAD",
+            TokenType::Plane => "PLANE",
+            TokenType::Audio => "AUDIO",
+            TokenType::Pitch => "PITCH",
+            TokenType::DelayTimer => "DT",
+            TokenType::SoundTimer => "ST",
+            TokenType::Key => "K",
+            TokenType::RegI => "I",
+            TokenType::IndirectI => "[I]",
+            TokenType::Sprite => "F",
+            TokenType::BigSprite => "HF",
+            TokenType::Bcd => "B",
+            TokenType::Flags => "R",
+            TokenType::Long => "LONG",
+            TokenType::Newline => "end of line",
+            TokenType::MacroEnd => "end of macro",
+            TokenType::Eof => "end of file",
+        };
+        write!(f, "{}", text)
+    }
+}
@@ -0,0 +1,66 @@
+// XO-CHIP audio:
+// the sound is a buffer of 128 1-bit samples, looped for as long as
+// the sound timer is running, at a playback rate set by the pitch register.
+// The emulation loop updates the shared pattern every frame
+// while rodio pulls samples from it on the audio thread.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rodio::Source;
+
+const SAMPLE_RATE: u32 = 44_100;
+const VOLUME: f32 = 0.25;
+
+pub struct Pattern {
+    pub bits: [u8; 16],
+    pub rate: f32,
+}
+
+pub struct PatternSource {
+    pattern: Arc<Mutex<Pattern>>,
+    position: f32,
+}
+
+impl PatternSource {
+    pub fn new(pattern: Arc<Mutex<Pattern>>) -> Self {
+        Self {
+            pattern,
+            position: 0.0,
+        }
+    }
+}
+
+impl Iterator for PatternSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let pattern = self.pattern.lock().unwrap();
+        let bit = self.position as usize % 128;
+        let sample = if pattern.bits[bit / 8] >> (7 - bit % 8) & 0x01 == 1 {
+            VOLUME
+        } else {
+            -VOLUME
+        };
+        self.position = (self.position + pattern.rate / SAMPLE_RATE as f32) % 128.0;
+        Some(sample)
+    }
+}
+
+impl Source for PatternSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
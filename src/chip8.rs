@@ -1,40 +1,180 @@
 // use core::time;
-use std::{fs::File, io::Read};
-use std::{thread, time::Duration};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 use rodio::{OutputStream, Sink};
 
 use minifb::{Key, KeyRepeat, Window, WindowOptions};
 
-use crate::bus::Bus;
-use crate::cpu::Cpu;
+use chiprs::gdb::GdbStub;
+use chiprs::machine::{Machine, DEFAULT_CYCLES_PER_FRAME};
+use chiprs::movie::Movie;
+use chiprs::rewind::RewindBuffer;
+use chiprs::scheduler::FrameScheduler;
+use chiprs::trace::{TraceFilter, TraceFormat, Tracer};
+use chiprs::{Quirks, Result};
+
+use crate::audio::{Pattern, PatternSource};
+use crate::console::Console;
+use crate::screenshot;
+use crate::terminal::{Charset, TerminalScreen};
+use crate::tui::Tui;
 
 const SCREEN_WIDTH: usize = 640;
 const SCREEN_HEIGHT: usize = 320;
 
+// Colours for each combination of the two XO-CHIP bitplanes: off, plane 1, plane 2, both
+pub const PALETTE: [u32; 4] = [0x000000, 0x00ff00, 0xff8800, 0xffffff];
+
+// Settings of the windowed frontend, filled from the command line
+pub struct Options {
+    pub quirks: Quirks,
+    pub cycles_per_frame: usize,
+    // Fixed seed of the random number generator, random by default
+    pub seed: Option<u64>,
+    // Memory budget of the rewind history in bytes, 0 disables rewinding
+    pub rewind_budget: usize,
+    // Start paused in the debugger console
+    pub debug: bool,
+    // Start halted, waiting for a GDB client on this local port
+    pub gdb_port: Option<u16>,
+    // Start paused in the terminal UI debugger
+    pub tui: bool,
+    // Log every executed instruction to this file
+    pub trace_file: Option<String>,
+    pub trace_format: TraceFormat,
+    pub trace_filter: TraceFilter,
+    // Record the keypad of every frame to this movie file
+    pub record: Option<String>,
+    // Play back a movie file instead of reading the keyboard
+    pub replay: Option<String>,
+    // Scale of PNG screenshots
+    pub screenshot_scale: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            quirks: Quirks::default(),
+            cycles_per_frame: DEFAULT_CYCLES_PER_FRAME,
+            seed: None,
+            rewind_budget: 16 * 1024 * 1024,
+            debug: false,
+            gdb_port: None,
+            tui: false,
+            trace_file: None,
+            trace_format: TraceFormat::Text,
+            trace_filter: TraceFilter::default(),
+            record: None,
+            replay: None,
+            screenshot_scale: screenshot::DEFAULT_SCALE,
+        }
+    }
+}
+
+// A movie being recorded or played back, see `chiprs::movie`
+enum MovieMode {
+    Recording { path: String, movie: Movie },
+    Replaying(Movie),
+}
+
 pub struct Chip8 {
-    bus: Bus,
-    cpu: Cpu,
+    machine: Machine,
+    rom_file: String,
+    rewind: RewindBuffer,
+    console: Option<Console>,
+    gdb: Option<GdbStub>,
+    tui: Option<Tui>,
+    movie: Option<MovieMode>,
+    screenshot_scale: usize,
 }
 
 impl Chip8 {
-    pub fn new(rom_file: String) -> Chip8 {
+    pub fn new(rom_file: String, options: Options) -> Result<Chip8> {
         let mut rom_buffer = Vec::<u8>::new();
-        let mut file = File::open(&rom_file).expect("File not found");
+        let mut file = File::open(&rom_file)?;
 
-        if let Ok(bytes_read) = file.read_to_end(&mut rom_buffer) {
-            println!("{} bytes loaded", bytes_read);
-        } else {
-            println!("Error loading ROM");
-        };
+        let bytes_read = file.read_to_end(&mut rom_buffer)?;
+        println!("{} bytes loaded", bytes_read);
 
-        Chip8 {
-            bus: Bus::new(),
-            cpu: Cpu::new(&rom_buffer),
+        let (mut machine, movie) = match (&options.replay, options.record) {
+            (Some(path), _) => {
+                let movie = Movie::from_bytes(&fs::read(path)?)?;
+                println!(
+                    "Replaying {} frames with the recorded quirks and speed",
+                    movie.len()
+                );
+                (
+                    movie.machine(&rom_buffer)?,
+                    Some(MovieMode::Replaying(movie)),
+                )
+            }
+            (None, record) => {
+                let mut machine = Machine::with_quirks(&rom_buffer, options.quirks)?;
+                machine.set_cycles_per_frame(options.cycles_per_frame);
+                // A recording always needs a known seed to be replayed
+                let seed = match (options.seed, &record) {
+                    (Some(seed), _) => Some(seed),
+                    (None, Some(_)) => Some(rand::random()),
+                    (None, None) => None,
+                };
+                if let Some(seed) = seed {
+                    machine.set_seed(seed);
+                }
+                let quirks = options.quirks;
+                let movie = record.map(|path| MovieMode::Recording {
+                    path,
+                    movie: Movie::new(
+                        &rom_buffer,
+                        quirks,
+                        machine.cycles_per_frame(),
+                        seed.unwrap_or_default(),
+                    ),
+                });
+                (machine, movie)
+            }
+        };
+        // The RPL flags left by previous runs would make movies play back differently
+        if movie.is_none() {
+            if let Some(flags) = load_rpl_flags(&rom_file) {
+                machine.set_rpl_flags(flags);
+            }
+        }
+        if let Some(path) = &options.trace_file {
+            let tracer = Tracer::create(path, options.trace_format, options.trace_filter)?;
+            machine.set_tracer(Some(tracer));
         }
+
+        let gdb = match options.gdb_port {
+            Some(port) => {
+                let gdb = GdbStub::bind(port)?;
+                println!("Waiting for GDB to connect on 127.0.0.1:{}", gdb.port()?);
+                Some(gdb)
+            }
+            None => None,
+        };
+
+        Ok(Chip8 {
+            machine,
+            rom_file,
+            rewind: RewindBuffer::new(options.rewind_budget),
+            console: if options.debug {
+                Some(Console::new())
+            } else {
+                None
+            },
+            gdb,
+            tui: if options.tui { Some(Tui::new()?) } else { None },
+            movie,
+            screenshot_scale: options.screenshot_scale,
+        })
     }
 
-    pub fn run(&mut self) {
+    // Runs until the window is closed or the program exits,
+    // execution errors stop the emulation and are returned to the caller
+    pub fn run(&mut self) -> Result<()> {
         let mut window = Window::new(
             "CHIP8RS",
             SCREEN_WIDTH,
@@ -44,99 +184,333 @@ impl Chip8 {
         .unwrap_or_else(|e| {
             panic!("Window creation failed: {:?}", e);
         });
+        // Frames are paced by the scheduler, not by minifb
+        window.limit_update_rate(None);
 
         // Sound
         let (_stream, stream_handle) = OutputStream::try_default().unwrap();
         let sink = Sink::try_new(&stream_handle).unwrap();
-        let source = rodio::source::SineWave::new(400);
-        sink.append(source);
+        let pattern = Arc::new(Mutex::new(Pattern {
+            bits: *self.machine.audio_pattern(),
+            rate: self.machine.audio_playback_rate(),
+        }));
+        if self.machine.quirks().xo_chip {
+            sink.append(PatternSource::new(Arc::clone(&pattern)));
+        } else {
+            sink.append(rodio::source::SineWave::new(400));
+        }
         sink.pause();
 
         let mut buffer: Vec<u32> = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let mut scheduler = FrameScheduler::default();
 
-        let mut clock = 0;
-
-        while window.is_open() && !window.is_key_down(Key::Escape) {
-            self.execute_cycle();
-
-            clock += 1;
-
-            // 30'ish Hz refresh rate
-            // Duration in nanoseconds
-            thread::sleep(Duration::new(0, 1_000_000_000u32 / 600 / 3));
-
-            let key = self.check_key(window.get_keys_pressed(KeyRepeat::Yes));
-            if key.is_some() {
-                self.set_key_pressed(key);
+        while window.is_open() && !window.is_key_down(Key::Escape) && !self.machine.has_exited() {
+            self.machine.set_keys(self.check_keys(window.get_keys()));
+            self.check_state_keys(&window);
+            if window.is_key_pressed(Key::P, KeyRepeat::No) {
+                println!("{}", self.take_screenshot());
             }
 
-            if clock % 10 == 0 {
-                self.cpu.update_timers();
+            // F12 breaks into the debugger
+            if window.is_key_pressed(Key::F12, KeyRepeat::No) {
+                if let Some(console) = &mut self.console {
+                    console.pause();
+                }
+            }
+            // Holding backspace runs the game backwards, one recorded frame per frame
+            if !self.emulate_frame(window.is_key_down(Key::Backspace))? {
+                break;
             }
-            if self.should_redraw() {
+
+            if self.machine.should_redraw() {
                 buffer = self.update_display(&buffer);
                 window
                     .update_with_buffer(&buffer, SCREEN_WIDTH, SCREEN_HEIGHT)
                     .unwrap();
+            } else {
+                window.update();
             }
-            if self.should_beep() {
+            if self.machine.audio_active() {
+                let mut pattern = pattern.lock().unwrap();
+                pattern.bits = *self.machine.audio_pattern();
+                pattern.rate = self.machine.audio_playback_rate();
                 sink.play();
             } else {
                 sink.pause();
             }
+
+            scheduler.wait_for_next_frame();
         }
+
+        self.save_rpl_flags();
+        self.save_movie();
+        Ok(())
     }
 
-    fn check_key(&self, keys_pressed: Option<Vec<Key>>) -> Option<Key> {
-        match keys_pressed {
-            Some(keys) => {
-                if !keys.is_empty() {
-                    Some(keys[0])
-                } else {
-                    None
+    // Same as `run`, drawing the display in the terminal instead of a window,
+    // for running over SSH or in containers. There is no audio, beeps ring the terminal bell
+    pub fn run_in_terminal(&mut self, charset: Charset) -> Result<()> {
+        let mut screen = TerminalScreen::new(charset)?;
+        let mut scheduler = FrameScheduler::default();
+        let mut beeping = false;
+        screen.draw(&self.machine)?;
+
+        while !self.machine.has_exited() {
+            let input = screen.poll_input()?;
+            if input.quit {
+                break;
+            }
+            self.machine.set_keys(input.keys);
+            for (slot, save) in input.state_slots {
+                let message = self.use_state_slot(slot, save);
+                screen.show_message(&message)?;
+            }
+            if input.screenshot {
+                let message = self.take_screenshot();
+                screen.show_message(&message)?;
+            }
+            if !self.emulate_frame(input.rewind)? {
+                break;
+            }
+
+            if self.machine.should_redraw() {
+                screen.draw(&self.machine)?;
+            }
+            // Ring once at the start of each beep
+            let active = self.machine.audio_active();
+            if active && !beeping {
+                screen.bell()?;
+            }
+            beeping = active;
+
+            scheduler.wait_for_next_frame();
+        }
+
+        self.save_rpl_flags();
+        self.save_movie();
+        Ok(())
+    }
+
+    // Run one frame, under the debugger if one is enabled, or step back one frame when rewinding
+    // Returns false once the debugger quits, execution errors end the emulation
+    fn emulate_frame(&mut self, rewinding: bool) -> Result<bool> {
+        // Going back in time would desynchronize the movie
+        if rewinding && self.movie.is_none() {
+            self.rewind_frame();
+            return Ok(true);
+        }
+        let frame = self.machine.frame_count();
+        if let Some(MovieMode::Replaying(movie)) = &self.movie {
+            match movie.keys(frame) {
+                Some(keys) => self.machine.set_keys(keys),
+                None => {
+                    println!("Replay finished after {} frames", frame);
+                    self.movie = None;
                 }
             }
-            None => None,
+        }
+        let result = if let Some(console) = &mut self.console {
+            Ok(console.run_frame(&mut self.machine))
+        } else if let Some(tui) = &mut self.tui {
+            tui.run_frame(&mut self.machine)
+        } else if let Some(gdb) = &mut self.gdb {
+            gdb.run_frame(&mut self.machine)
+        } else {
+            self.machine.run_frame().map(|_| true)
+        };
+        // Debuggers may stop in the middle of a frame, only record completed ones
+        if let Some(MovieMode::Recording { movie, .. }) = &mut self.movie {
+            if self.machine.frame_count() > frame {
+                movie.push(self.machine.keys());
+            }
+        }
+        match result {
+            Ok(true) => {
+                // Nothing to record while a debugger holds the program
+                if self.machine.frame_count() > frame {
+                    self.rewind.push(self.machine.save_state());
+                }
+                Ok(true)
+            }
+            Ok(false) => Ok(false),
+            Err(e) => {
+                self.save_rpl_flags();
+                self.save_movie();
+                Err(e)
+            }
+        }
+    }
+
+    // Runs a number of frames without any window or terminal display,
+    // then saves a screenshot of the display
+    pub fn run_headless(&mut self, frames: u64, screenshot: &Path) -> Result<()> {
+        for _ in 0..frames {
+            if self.machine.has_exited() || !self.emulate_frame(false)? {
+                break;
+            }
+        }
+        self.save_rpl_flags();
+        self.save_movie();
+        screenshot::save(&self.machine, screenshot, self.screenshot_scale)?;
+        println!("Screenshot saved to {}", screenshot.display());
+        Ok(())
+    }
+
+    // P saves the display next to the ROM, both as a PNG image and as ASCII art.
+    // Returns the message for the frontend to show
+    fn take_screenshot(&self) -> String {
+        let (png, txt) = screenshot::next_paths(&self.rom_file);
+        let result = screenshot::save(&self.machine, &png, self.screenshot_scale)
+            .and_then(|_| screenshot::save(&self.machine, &txt, self.screenshot_scale));
+        match result {
+            Ok(()) => format!("Screenshot saved to {}", png.display()),
+            Err(e) => format!("Error saving screenshot: {}", e),
+        }
+    }
+
+    fn rewind_frame(&mut self) {
+        if let Some(state) = self.rewind.pop() {
+            if let Err(e) = self.machine.load_state(&state) {
+                println!("Error rewinding: {}", e);
+            }
+        }
+    }
+
+    // Save states: Shift+F1-F9 saves to the numbered slot, F1-F9 loads it back
+    fn check_state_keys(&mut self, window: &Window) {
+        let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+        let keys = window.get_keys_pressed(KeyRepeat::No).unwrap_or_default();
+        for slot in keys.into_iter().filter_map(state_slot) {
+            println!("{}", self.use_state_slot(slot, shift));
+        }
+    }
+
+    // Returns the message for the frontend to show
+    fn use_state_slot(&mut self, slot: u8, save: bool) -> String {
+        let path = self.state_path(slot);
+        if save {
+            match fs::write(&path, self.machine.save_state()) {
+                Ok(()) => format!("State saved to slot {}", slot),
+                Err(e) => format!("Error saving state: {}", e),
+            }
+        } else if self.movie.is_some() {
+            "Loading states is disabled while recording or replaying a movie".to_string()
+        } else {
+            match fs::read(&path)
+                .map_err(chiprs::Error::from)
+                .and_then(|bytes| self.machine.load_state(&bytes))
+            {
+                Ok(()) => format!("State loaded from slot {}", slot),
+                Err(e) => format!("Error loading state: {}", e),
+            }
+        }
+    }
+
+    // Save states are kept next to the ROM: <rom>.ss1 to <rom>.ss9
+    fn state_path(&self, slot: u8) -> PathBuf {
+        Path::new(&self.rom_file).with_extension(format!("ss{}", slot))
+    }
+
+    fn save_rpl_flags(&self) {
+        if let Err(e) = save_rpl_flags(&self.rom_file, &self.machine.rpl_flags()) {
+            println!("Error saving RPL flags: {}", e);
+        }
+    }
+
+    fn save_movie(&mut self) {
+        if let Some(MovieMode::Recording { path, movie }) = &self.movie {
+            match fs::write(path, movie.to_bytes()) {
+                Ok(()) => println!("Movie of {} frames saved to {}", movie.len(), path),
+                Err(e) => println!("Error saving movie: {}", e),
+            }
         }
     }
 
+    // Keypad state as a bitmask of the held keys
+    fn check_keys(&self, keys_down: Option<Vec<Key>>) -> u16 {
+        keys_down
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(decode_key)
+            .fold(0, |keys, key| keys | 1 << key)
+    }
+
     fn update_display(&self, in_buffer: &[u32]) -> Vec<u32> {
         let mut buffer = in_buffer.to_owned();
-        let chip8_buffer = self.get_frame_buffer();
+        let chip8_buffer = self.machine.framebuffer();
+        let (width, height) = self.machine.display_size();
+        let (scale_x, scale_y) = (SCREEN_WIDTH / width, SCREEN_HEIGHT / height);
         for y in 0..SCREEN_HEIGHT {
             for x in 0..SCREEN_WIDTH {
-                let index = self.get_frame_index(x / 10, y / 10);
+                let index = x / scale_x + width * (y / scale_y);
                 let pixel = chip8_buffer[index];
 
-                let color = if pixel == 1 { 0x00ff00 } else { 0x0 };
+                let color = PALETTE[pixel as usize & 0x3];
                 let offset = y * SCREEN_WIDTH + x;
                 buffer[offset] = color;
             }
         }
         buffer
     }
+}
 
-    fn execute_cycle(&mut self) {
-        self.cpu.execute_cycle(&mut self.bus);
-    }
+// SUPER-CHIP RPL user flags persist between runs in a `.rpl` file next to the ROM
+fn rpl_path(rom_file: &str) -> PathBuf {
+    Path::new(rom_file).with_extension("rpl")
+}
 
-    fn get_frame_buffer(&self) -> &[u8] {
-        self.bus.display.get_frame_buffer()
-    }
+fn load_rpl_flags(rom_file: &str) -> Option<[u8; 16]> {
+    let bytes = fs::read(rpl_path(rom_file)).ok()?;
+    let mut flags = [0; 16];
+    let len = bytes.len().min(16);
+    flags[..len].copy_from_slice(&bytes[..len]);
+    Some(flags)
+}
 
-    fn get_frame_index(&self, x: usize, y: usize) -> usize {
-        self.bus.display.get_index(x, y)
+fn save_rpl_flags(rom_file: &str, flags: &[u8; 16]) -> std::io::Result<()> {
+    // Don't litter the ROM directory for programs which never used the flags
+    if flags.iter().all(|flag| *flag == 0) && !rpl_path(rom_file).exists() {
+        return Ok(());
     }
+    fs::write(rpl_path(rom_file), flags)
+}
 
-    fn set_key_pressed(&mut self, key: Option<Key>) {
-        self.bus.set_key_pressed(key);
+fn state_slot(key: Key) -> Option<u8> {
+    match key {
+        Key::F1 => Some(1),
+        Key::F2 => Some(2),
+        Key::F3 => Some(3),
+        Key::F4 => Some(4),
+        Key::F5 => Some(5),
+        Key::F6 => Some(6),
+        Key::F7 => Some(7),
+        Key::F8 => Some(8),
+        Key::F9 => Some(9),
+        _ => None,
     }
+}
 
-    fn should_redraw(&self) -> bool {
-        self.cpu.should_redraw()
-    }
+fn decode_key(key: Key) -> Option<u8> {
+    match key {
+        Key::Key1 => Some(0x1),
+        Key::Key2 => Some(0x2),
+        Key::Key3 => Some(0x3),
+        Key::Key4 => Some(0xC),
+
+        Key::Q => Some(0x4),
+        Key::W => Some(0x5),
+        Key::E => Some(0x6),
+        Key::R => Some(0xD),
+
+        Key::A => Some(0x7),
+        Key::S => Some(0x8),
+        Key::D => Some(0x9),
+        Key::F => Some(0xE),
 
-    fn should_beep(&self) -> bool {
-        self.cpu.should_beep()
+        Key::Z => Some(0xA),
+        Key::X => Some(0x0),
+        Key::C => Some(0xB),
+        Key::V => Some(0xF),
+        _ => None,
     }
 }
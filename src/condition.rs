@@ -0,0 +1,357 @@
+// Conditional breakpoint expressions, e.g. `V3 == 0x10 && I > 0x300`:
+//   operands: V0-VF, I, PC, DT, ST, SP, a byte of memory [expr],
+//             decimal or 0x-prefixed hexadecimal numbers
+//   operators, by increasing precedence: ||, &&, == != < <= > >=, unary !
+//   and parentheses. Values are unsigned, anything non-zero is true.
+
+use std::fmt;
+
+use crate::cpu::Cpu;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Register {
+    V(usize),
+    I,
+    Pc,
+    Dt,
+    St,
+    Sp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(usize),
+    Register(Register),
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(Operator, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(usize),
+    Register(Register),
+    Operator(Operator),
+    Not,
+    Open,
+    Close,
+    OpenBracket,
+    CloseBracket,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "number {}", value),
+            Token::Register(register) => write!(f, "register {:?}", register),
+            Token::Operator(operator) => write!(f, "operator {:?}", operator),
+            Token::Not => write!(f, "'!'"),
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+            Token::OpenBracket => write!(f, "'['"),
+            Token::CloseBracket => write!(f, "']'"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    source: String,
+    expr: Expr,
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Condition, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+        };
+        let expr = parser.or()?;
+        if parser.position < tokens.len() {
+            return Err(format!("unexpected {}", tokens[parser.position]));
+        }
+        Ok(Condition {
+            source: source.trim().to_string(),
+            expr,
+        })
+    }
+
+    pub fn is_true(&self, cpu: &Cpu) -> bool {
+        evaluate(&self.expr, cpu) != 0
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+fn evaluate(expr: &Expr, cpu: &Cpu) -> usize {
+    match expr {
+        Expr::Number(value) => *value,
+        Expr::Register(register) => match register {
+            Register::V(idx) => cpu.v[*idx] as usize,
+            Register::I => cpu.i,
+            Register::Pc => cpu.pc,
+            Register::Dt => cpu.delay_timer() as usize,
+            Register::St => cpu.sound_timer() as usize,
+            Register::Sp => cpu.stack().len(),
+        },
+        // Out of bounds reads evaluate to 0 rather than failing the breakpoint
+        Expr::Memory(address) => cpu.ram.peek_byte(evaluate(address, cpu)).unwrap_or(0) as usize,
+        Expr::Not(expr) => (evaluate(expr, cpu) == 0) as usize,
+        Expr::Binary(operator, left, right) => {
+            let left = evaluate(left, cpu);
+            // Short-circuit like the Rust operators they are written as
+            match operator {
+                Operator::Or if left != 0 => return 1,
+                Operator::And if left == 0 => return 0,
+                _ => {}
+            }
+            let right = evaluate(right, cpu);
+            let result = match operator {
+                Operator::Or | Operator::And => right != 0,
+                Operator::Equal => left == right,
+                Operator::NotEqual => left != right,
+                Operator::Less => left < right,
+                Operator::LessEqual => left <= right,
+                Operator::Greater => left > right,
+                Operator::GreaterEqual => left >= right,
+            };
+            result as usize
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut idx = 0;
+    while idx < chars.len() {
+        let c = chars[idx];
+        let next = chars.get(idx + 1).copied();
+        let (token, len) = match (c, next) {
+            (' ' | '\t', _) => {
+                idx += 1;
+                continue;
+            }
+            ('|', Some('|')) => (Token::Operator(Operator::Or), 2),
+            ('&', Some('&')) => (Token::Operator(Operator::And), 2),
+            ('=', Some('=')) => (Token::Operator(Operator::Equal), 2),
+            ('!', Some('=')) => (Token::Operator(Operator::NotEqual), 2),
+            ('<', Some('=')) => (Token::Operator(Operator::LessEqual), 2),
+            ('>', Some('=')) => (Token::Operator(Operator::GreaterEqual), 2),
+            ('<', _) => (Token::Operator(Operator::Less), 1),
+            ('>', _) => (Token::Operator(Operator::Greater), 1),
+            ('!', _) => (Token::Not, 1),
+            ('(', _) => (Token::Open, 1),
+            (')', _) => (Token::Close, 1),
+            ('[', _) => (Token::OpenBracket, 1),
+            (']', _) => (Token::CloseBracket, 1),
+            _ if c.is_ascii_alphanumeric() => {
+                let len = chars[idx..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphanumeric())
+                    .count();
+                let word: String = chars[idx..idx + len].iter().collect();
+                (word_token(&word)?, len)
+            }
+            _ => return Err(format!("unexpected character '{}'", c)),
+        };
+        tokens.push(token);
+        idx += len;
+    }
+    Ok(tokens)
+}
+
+fn word_token(word: &str) -> Result<Token, String> {
+    let upper = word.to_ascii_uppercase();
+    let register = match upper.as_str() {
+        "I" => Some(Register::I),
+        "PC" => Some(Register::Pc),
+        "DT" => Some(Register::Dt),
+        "ST" => Some(Register::St),
+        "SP" => Some(Register::Sp),
+        _ if upper.len() == 2 && upper.starts_with('V') => {
+            usize::from_str_radix(&upper[1..], 16).ok().map(Register::V)
+        }
+        _ => None,
+    };
+    if let Some(register) = register {
+        return Ok(Token::Register(register));
+    }
+
+    let number = match upper.strip_prefix("0X") {
+        Some(digits) => usize::from_str_radix(digits, 16),
+        None => upper.parse(),
+    };
+    number
+        .map(Token::Number)
+        .map_err(|_| format!("unknown operand '{}'", word))
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if *token == expected => Ok(()),
+            Some(token) => Err(format!("expected {}, found {}", expected, token)),
+            None => Err(format!("expected {} at the end", expected)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut left = self.and()?;
+        while self.peek() == Some(&Token::Operator(Operator::Or)) {
+            self.position += 1;
+            left = Expr::Binary(Operator::Or, Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut left = self.comparison()?;
+        while self.peek() == Some(&Token::Operator(Operator::And)) {
+            self.position += 1;
+            left = Expr::Binary(Operator::And, Box::new(left), Box::new(self.comparison()?));
+        }
+        Ok(left)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let left = self.unary()?;
+        match self.peek() {
+            Some(Token::Operator(operator))
+                if *operator != Operator::Or && *operator != Operator::And =>
+            {
+                self.position += 1;
+                Ok(Expr::Binary(
+                    *operator,
+                    Box::new(left),
+                    Box::new(self.unary()?),
+                ))
+            }
+            _ => Ok(left),
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Number(value)) => Ok(Expr::Number(*value)),
+            Some(Token::Register(register)) => Ok(Expr::Register(*register)),
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                self.expect(Token::Close)?;
+                Ok(expr)
+            }
+            Some(Token::OpenBracket) => {
+                let expr = self.or()?;
+                self.expect(Token::CloseBracket)?;
+                Ok(Expr::Memory(Box::new(expr)))
+            }
+            Some(token) => Err(format!("unexpected {}", token)),
+            None => Err("incomplete expression".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::Machine;
+
+    fn machine() -> Machine {
+        let mut machine = Machine::new(&[0x12, 0x00]).unwrap();
+        let cpu = machine.cpu_mut();
+        cpu.v[3] = 0x10;
+        cpu.i = 0x310;
+        cpu.ram.memory[0x310] = 0xAB;
+        machine
+    }
+
+    fn is_true(source: &str, machine: &Machine) -> bool {
+        Condition::parse(source).unwrap().is_true(machine.cpu())
+    }
+
+    #[test]
+    fn evaluates_register_comparisons() {
+        let mut machine = machine();
+        assert!(is_true("V3 == 0x10 && I > 0x300", &machine));
+        machine.cpu_mut().i = 0x300;
+        assert!(!is_true("V3 == 0x10 && I > 0x300", &machine));
+        assert!(is_true("v3 >= 16 && i <= 768 && pc == 0x200", &machine));
+        assert!(is_true(
+            "V3 != 0x11 && SP < 1 && DT == 0 && ST == 0",
+            &machine
+        ));
+    }
+
+    #[test]
+    fn reads_memory() {
+        let machine = machine();
+        assert!(is_true("[I] == 0xAB", &machine));
+        assert!(is_true("[0x311] == 0", &machine));
+        assert!(is_true("[0x10000] == 0", &machine));
+    }
+
+    #[test]
+    fn follows_precedence() {
+        let machine = machine();
+        // && binds tighter than ||
+        assert!(is_true("V3 == 0x10 || V0 == 1 && V1 == 1", &machine));
+        assert!(!is_true("(V3 == 0x10 || V0 == 1) && V1 == 1", &machine));
+        // ! binds tighter than the comparisons
+        assert!(is_true("!V0 == 1", &machine));
+        assert!(!is_true("!(V3 == 0x10)", &machine));
+        assert_eq!(
+            Condition::parse("V0 < 1 == 1").map(|_| ()),
+            Err("unexpected operator Equal".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        for source in [
+            "",
+            "V3 ==",
+            "V3 = 0x10",
+            "(V3 == 0x10",
+            "V3 == 0x10)",
+            "[I == 1",
+            "VG == 1",
+            "V3 == 0xZZ",
+            "V3 == 1 &&& V2",
+        ] {
+            assert!(Condition::parse(source).is_err(), "`{}`", source);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+// Debugger console:
+// while the debugger is paused, commands are read from the terminal
+// and the machine state is printed at each stop.
+// The emulation loop keeps ownership of the window, reading a command blocks it.
+
+use std::io::{self, BufRead, Write};
+
+use chiprs::debugger::{self, Command, Debugger, HELP};
+use chiprs::ram::Access;
+use chiprs::Machine;
+
+const LISTING_LENGTH: usize = 10;
+
+pub struct Console {
+    debugger: Debugger,
+    last_command: Option<Command>,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        println!("Debugger started, type 'help' for the list of commands");
+        Self {
+            debugger: Debugger::new(),
+            last_command: None,
+        }
+    }
+
+    pub fn pause(&mut self) {
+        self.debugger.pause();
+    }
+
+    // Run one frame under the debugger, prompting for commands first if paused
+    // Returns false once the user quits
+    pub fn run_frame(&mut self, machine: &mut Machine) -> bool {
+        if self.debugger.is_paused() && !self.prompt(machine) {
+            return false;
+        }
+        if let Err(e) = self.debugger.run_frame(machine) {
+            println!("Error: {}", e);
+        }
+        true
+    }
+
+    // Read commands until one of them resumes execution
+    fn prompt(&mut self, machine: &mut Machine) -> bool {
+        if let Some(stop) = self.debugger.take_stop() {
+            println!("{}", stop);
+        }
+        println!("{}", debugger::status(machine));
+        let stdin = io::stdin();
+        loop {
+            print!("(chip8rs) ");
+            let _ = io::stdout().flush();
+
+            let mut line = String::new();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) | Err(_) => return false,
+                Ok(_) => {}
+            }
+            let command = if line.trim().is_empty() {
+                match &self.last_command {
+                    Some(command) => command.clone(),
+                    None => continue,
+                }
+            } else {
+                match Command::parse(&line) {
+                    Ok(command) => command,
+                    Err(message) => {
+                        println!("{}", message);
+                        continue;
+                    }
+                }
+            };
+            self.last_command = Some(command.clone());
+
+            match command {
+                Command::Continue => self.debugger.resume(),
+                Command::Step => self.debugger.step(),
+                Command::Next => self.debugger.step_over(machine),
+                Command::RunTo(address) => self.debugger.run_to(address),
+                Command::Break(address) => {
+                    if self.debugger.add_breakpoint(address) {
+                        println!("Breakpoint set at {:#05X}", address);
+                    } else {
+                        println!("Breakpoint already set at {:#05X}", address);
+                    }
+                }
+                Command::Delete(address) => {
+                    if self.debugger.remove_breakpoint(address) {
+                        println!("Breakpoint at {:#05X} removed", address);
+                    } else {
+                        println!("No breakpoint at {:#05X}", address);
+                    }
+                }
+                Command::Watch(watchpoint) => {
+                    machine.add_watchpoint(watchpoint);
+                    println!(
+                        "Watchpoint set on {:#05X}-{:#05X}",
+                        watchpoint.start, watchpoint.end
+                    );
+                }
+                Command::Unwatch(address) => {
+                    if machine.remove_watchpoint(address) {
+                        println!("Watchpoint at {:#05X} removed", address);
+                    } else {
+                        println!("No watchpoint at {:#05X}", address);
+                    }
+                }
+                Command::When(condition) => {
+                    println!("Stopping when {}", condition);
+                    self.debugger.add_condition(condition, machine);
+                }
+                Command::Unwhen(idx) => match self.debugger.remove_condition(idx) {
+                    Some(condition) => println!("Condition {} removed", condition),
+                    None => println!("No condition numbered {}", idx),
+                },
+                Command::Breakpoints => {
+                    for address in self.debugger.breakpoints() {
+                        println!("break {:#05X}", address);
+                    }
+                    for watchpoint in machine.watchpoints() {
+                        let command = match watchpoint.access {
+                            Access::Read => "rwatch",
+                            Access::Write => "watch",
+                            Access::ReadWrite => "awatch",
+                        };
+                        println!(
+                            "{} {:#05X}-{:#05X}",
+                            command, watchpoint.start, watchpoint.end
+                        );
+                    }
+                    for (idx, condition) in self.debugger.conditions().enumerate() {
+                        println!("when #{}: {}", idx, condition);
+                    }
+                }
+                Command::Registers => println!("{}", debugger::registers(machine)),
+                Command::Stack => println!("{}", debugger::stack(machine)),
+                Command::Disassemble(address) => {
+                    let address = address.unwrap_or(machine.cpu().pc);
+                    print!("{}", debugger::listing(machine, address, LISTING_LENGTH));
+                }
+                Command::Help => println!("{}", HELP),
+                Command::Quit => return false,
+            }
+            if !self.debugger.is_paused() {
+                return true;
+            }
+        }
+    }
+}
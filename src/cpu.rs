@@ -1,47 +1,109 @@
 use crate::bus::Bus;
-use crate::ram::Ram;
-use rand::Rng;
+use crate::error::{Error, Result};
+use crate::font;
+use crate::quirks::Quirks;
+use crate::ram::{Ram, RAM_SIZE, XO_RAM_SIZE};
+use crate::rng::Rng;
+use crate::snapshot::Snapshot;
+use crate::trace::Tracer;
 use std::fmt;
+
+// The original interpreters reserved room for 16 return addresses
+pub const STACK_SIZE: usize = 16;
+
 pub struct Cpu {
     pub ram: Ram,
     pub pc: usize,
     pub v: [u8; 16],
     pub i: usize,
+    pub quirks: Quirks,
     stack: Vec<usize>,
     delay_timer: u8,
     sound_timer: u8,
     draw_flag: bool,
+    // SUPER-CHIP RPL user flags, saved/restored with FX75/FX85
+    rpl: [u8; 16],
+    exited: bool,
+    // FX0A halts the CPU until a key is pressed and released,
+    // holding the key seen going down while waiting for its release
+    waiting_for_key: bool,
+    key_down: Option<u8>,
+    // XO-CHIP audio: 128 1-bit samples played back at a rate set by the pitch register
+    audio_pattern: [u8; 16],
+    pitch: u8,
+    rng: Rng,
+    tracer: Option<Tracer>,
     // paused: bool,
 }
 
 impl Cpu {
-    pub fn new(rom_buffer: &[u8]) -> Self {
-        Self {
-            ram: Ram::new(rom_buffer),
+    pub fn new(rom_buffer: &[u8], quirks: Quirks) -> Result<Self> {
+        let ram_size = if quirks.xo_chip {
+            XO_RAM_SIZE
+        } else {
+            RAM_SIZE
+        };
+        Ok(Self {
+            ram: Ram::with_size(rom_buffer, ram_size)?,
             pc: 0x200,
             v: [0x00; 16],
             i: 0,
+            quirks,
             stack: Vec::new(),
             delay_timer: 0,
             sound_timer: 0,
             draw_flag: false,
+            rpl: [0; 16],
+            exited: false,
+            waiting_for_key: false,
+            key_down: None,
+            audio_pattern: [0; 16],
+            pitch: 64,
+            rng: Rng::from_entropy(),
+            tracer: None,
+        })
+    }
+
+    pub fn execute_cycle(&mut self, bus: &mut Bus) -> Result<()> {
+        self.draw_flag = false;
+        if self.exited {
+            return Ok(());
+        }
+        let opcode = self.fetch_op()?;
+        if let Some(mut tracer) = self.tracer.take() {
+            let traced = tracer.record(self, opcode);
+            self.tracer = Some(tracer);
+            traced?;
         }
+        self.decode_op(opcode, bus)
     }
 
-    pub fn execute_cycle(&mut self, bus: &mut Bus) {
-        self.draw_flag = false;
-        let opcode = self.fetch_op();
-        self.decode_op(opcode, bus);
+    // Restart the random number generator from a fixed seed, for reproducible runs
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    // Log every executed instruction, see `trace`
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
     }
 
-    fn fetch_op(&mut self) -> u16 {
-        // Load from self.pc (2 bytes), so fetch two successive bytes
-        let hi_byte = self.ram.read_byte(self.pc) as u16;
-        let lo_byte = self.ram.read_byte(self.pc + 1) as u16;
-        hi_byte << 8 | lo_byte
+    fn fetch_op(&mut self) -> Result<u16> {
+        self.fetch_op_at(self.pc)
     }
 
-    fn decode_op(&mut self, opcode: u16, bus: &mut Bus) {
+    fn fetch_op_at(&self, address: usize) -> Result<u16> {
+        // Load from address (2 bytes), so fetch two successive bytes
+        let hi_byte = self.ram.peek_byte(address)? as u16;
+        let lo_byte = self.ram.peek_byte(address + 1)? as u16;
+        Ok(hi_byte << 8 | lo_byte)
+    }
+
+    fn decode_op(&mut self, opcode: u16, bus: &mut Bus) -> Result<()> {
+        let invalid = Error::InvalidOpcode {
+            addr: self.pc,
+            opcode,
+        };
         // Break up 2byte opcode into nibbles and bytes
         let nibbles = (
             ((opcode & 0xF000) >> 12) as u8,
@@ -51,21 +113,30 @@ impl Cpu {
         );
         let x = nibbles.1 as usize;
         let y = nibbles.2 as usize;
-        let n = nibbles.3 as u8;
+        let n = nibbles.3;
         let kk = (opcode & 0x00FF) as u8;
         let nnn = opcode & 0x0FFF;
         match nibbles {
             //
             (0x00, _, _, _) => match kk {
+                0xC0..=0xCF => self.op_00cn(bus, n), // 00CN - SCD nibble: Scroll display down n lines (SUPER-CHIP)
+                0xD0..=0xDF if self.quirks.xo_chip => self.op_00dn(bus, n), // 00DN - SCU nibble: Scroll display up n lines (XO-CHIP)
                 0xE0 => self.op_00e0(bus), // 00E0 - CLS: Clear display
-                0xEE => self.op_00ee(),    // 00EE - RET : Return from subroutine
-                _ => println!("Unrecognized opcode {:?}", opcode),
+                0xEE => self.op_00ee()?,   // 00EE - RET : Return from subroutine
+                0xFB => self.op_00fb(bus), // 00FB - SCR: Scroll display right 4 pixels (SUPER-CHIP)
+                0xFC => self.op_00fc(bus), // 00FC - SCL: Scroll display left 4 pixels (SUPER-CHIP)
+                0xFD => self.op_00fd(),    // 00FD - EXIT: Exit the interpreter (SUPER-CHIP)
+                0xFE => self.op_00fe(bus), // 00FE - LOW: Switch to low resolution mode (SUPER-CHIP)
+                0xFF => self.op_00ff(bus), // 00FF - HIGH: Switch to high resolution mode (SUPER-CHIP)
+                _ => return Err(invalid),
             },
             (0x01, _, _, _) => self.op_1nnn(nnn), // 1NNN - JP addr: Jump to location nnn.
-            (0x02, _, _, _) => self.op_2nnn(nnn), // 2NNN - CALL addr: Call subroutine at nnn.
+            (0x02, _, _, _) => self.op_2nnn(nnn)?, // 2NNN - CALL addr: Call subroutine at nnn.
             (0x03, _, _, _) => self.op_3xkk(x, kk), // 3XKK - SE Vx, byte: Skip next instruction if Vx = kk.
             (0x04, _, _, _) => self.op_4xkk(x, kk), // 4XKK - SNE Vx, byte: Skip next instruction if Vx != kk.
-            (0x05, _, _, _) => self.op_5xy0(x, y), // 5XY0 - SE Vx, Vy: Skip next instruction if Vx = Vy.
+            (0x05, _, _, 0x00) => self.op_5xy0(x, y), // 5XY0 - SE Vx, Vy: Skip next instruction if Vx = Vy.
+            (0x05, _, _, 0x02) if self.quirks.xo_chip => self.op_5xy2(x, y)?, // 5XY2 - SAVE Vx, Vy: Store registers Vx through Vy in memory starting at location I (XO-CHIP).
+            (0x05, _, _, 0x03) if self.quirks.xo_chip => self.op_5xy3(x, y)?, // 5XY3 - LOAD Vx, Vy: Read registers Vx through Vy from memory starting at location I (XO-CHIP).
             (0x06, _, _, _) => self.op_6xkk(x, kk), // 6XKK - LD Vx, byte: Set Vx = kk.
             (0x07, _, _, _) => self.op_7xkk(x, kk), // 7XKK - ADD Vx, byte: Set Vx = Vx + kk.
             (0x08, _, _, _) => match n {
@@ -75,36 +146,45 @@ impl Cpu {
                 0x03 => self.op_8xy3(x, y), //  8XY3 - XOR Vx, Vy: Set Vx = Vx XOR Vy.
                 0x04 => self.op_8xy4(x, y), //  8XY4 - ADD Vx, Vy: Set Vx = Vx + Vy, set VF = carry.
                 0x05 => self.op_8xy5(x, y), //  8XY5 - SUB Vx, Vy: Set Vx = Vx - Vy, set VF = NOT borrow.
-                0x06 => self.op_8xy6(x),    //  8XY6 - SHR Vx: Set Vx = Vx SHR 1.
+                0x06 => self.op_8xy6(x, y), //  8XY6 - SHR Vx: Set Vx = Vx SHR 1.
                 0x07 => self.op_8xy7(x, y), //  8XY7 - SUBN Vx, Vy: Set Vx = Vy - Vx, set VF = NOT borrow.
-                0x0E => self.op_8xye(x),    //  8XYE - SHL Vx: Set Vx = Vx SHL 1.
-                _ => println!("Unrecognized opcode {:?}", opcode),
+                0x0E => self.op_8xye(x, y), //  8XYE - SHL Vx: Set Vx = Vx SHL 1.
+                _ => return Err(invalid),
             },
             (0x09, _, _, _) => self.op_9xy0(x, y), // 9XY0 - SNE Vx, Vy: Skip next instruction if Vx != Vy.
             (0x0A, _, _, _) => self.op_annn(nnn),  // ANNN - LD I, addr: Set I to NNN
             (0x0B, _, _, _) => self.op_bnnn(nnn),  // BNNN - JP V0, addr: Jump to location nnn + V0.
             (0x0C, _, _, _) => self.op_cxkk(x, kk), // CXKK - RND Vx, byte: Set Vx = random byte AND kk.
-            (0x0D, _, _, _) => self.op_dxyn(bus, x, y, n), // DXYN - DRW, Vx, Vy, nibble: Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
+            (0x0D, _, _, 0x00) => self.op_dxy0(bus, x, y)?, // DXY0 - DRW Vx, Vy, 0: Display 16x16 sprite starting at memory location I at (Vx, Vy), set VF = collision (SUPER-CHIP)
+            (0x0D, _, _, _) => self.op_dxyn(bus, x, y, n)?, // DXYN - DRW, Vx, Vy, nibble: Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
             (0x0E, _, _, _) => match kk {
                 0x9E => self.op_ex9e(x, bus), //  Ex9E - SKP Vx:  Skip next instruction if key with the value of Vx is pressed.
                 0xA1 => self.op_exa1(x, bus), //  EXA1 - SKNP Vx: Skip next instruction if key with the value of Vx is not pressed.
 
-                _ => println!("Unrecognized opcode {:?}", opcode),
+                _ => return Err(invalid),
             },
             (0x0F, _, _, _) => match kk {
+                0x00 if x == 0 && self.quirks.xo_chip => self.op_f000()?, //  F000 NNNN - LD I, long NNNN: Set I = NNNN, the 16-bit address following the opcode (XO-CHIP).
+                0x01 if self.quirks.xo_chip => self.op_fn01(bus, x), //  FN01 - PLANE n: Select the drawing planes given by bitmask n (XO-CHIP).
+                0x02 if x == 0 && self.quirks.xo_chip => self.op_f002()?, //  F002 - AUDIO: Load 16 bytes starting at I into the audio pattern buffer (XO-CHIP).
                 0x07 => self.op_fx07(x), //  FX07 - LD Vx, DT: Set Vx = delay timer value. The value of DT is placed into Vx.
                 0x0A => self.op_fx0a(x, bus), //  FX0A - LD Vx, K: Wait for a key press, store the value of the key in Vx.
                 0x15 => self.op_fx15(x),      //  FX15 - LD DT, Vx: Set delay timer = Vx.
                 0x18 => self.op_fx18(x),      //  FX18 - LD ST, Vx: Set sound timer = Vx.
                 0x1E => self.op_fx1e(x),      //  FX1E - ADD I, Vx: Set I = I + Vx.
                 0x29 => self.op_fx29(x), //  FX29 - LD F, Vx: Set I = location of sprite for digit Vx.
-                0x33 => self.op_fx33(x), //  FX33 - LD B, Vx: Store BCD representation of Vx in memory locations I, I+1, and I+2.
-                0x55 => self.op_fx55(x), //  FX55 - LD [I], Vx: Store registers V0 through Vx in memory starting at location I.
-                0x65 => self.op_fx65(x), //  FX65 - Ld Vx, [I]: Read registers V0 through Vx from memory starting at location I.
-                _ => println!("Unrecognized opcode {:?}", opcode),
+                0x30 => self.op_fx30(x), //  FX30 - LD HF, Vx: Set I = location of big sprite for digit Vx (SUPER-CHIP).
+                0x33 => self.op_fx33(x)?, //  FX33 - LD B, Vx: Store BCD representation of Vx in memory locations I, I+1, and I+2.
+                0x3A if self.quirks.xo_chip => self.op_fx3a(x), //  FX3A - PITCH Vx: Set the audio pattern playback rate from Vx (XO-CHIP).
+                0x55 => self.op_fx55(x)?, //  FX55 - LD [I], Vx: Store registers V0 through Vx in memory starting at location I.
+                0x65 => self.op_fx65(x)?, //  FX65 - Ld Vx, [I]: Read registers V0 through Vx from memory starting at location I.
+                0x75 => self.op_fx75(x), //  FX75 - LD R, Vx: Store registers V0 through Vx in the RPL user flags (SUPER-CHIP).
+                0x85 => self.op_fx85(x), //  FX85 - LD Vx, R: Read registers V0 through Vx from the RPL user flags (SUPER-CHIP).
+                _ => return Err(invalid),
             },
-            _ => println!("Unrecognized opcode {:?}", opcode),
+            _ => return Err(invalid),
         }
+        Ok(())
     }
 
     pub fn update_timers(&mut self) {
@@ -116,6 +196,27 @@ impl Cpu {
         }
     }
 
+    // Return addresses of the pending subroutine calls, innermost last
+    pub fn stack(&self) -> &[usize] {
+        &self.stack
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
     pub fn should_beep(&self) -> bool {
         self.sound_timer > 0
     }
@@ -124,15 +225,146 @@ impl Cpu {
         self.draw_flag
     }
 
+    pub(crate) fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.quirks = self.quirks.to_bits();
+        snapshot.pc = self.pc as u32;
+        snapshot.i = self.i as u32;
+        snapshot.v = self.v;
+        snapshot.stack = self.stack.iter().map(|address| *address as u32).collect();
+        snapshot.delay_timer = self.delay_timer;
+        snapshot.sound_timer = self.sound_timer;
+        snapshot.exited = self.exited;
+        snapshot.waiting_for_key = self.waiting_for_key;
+        snapshot.key_down = self.key_down;
+        snapshot.rpl = self.rpl;
+        snapshot.audio_pattern = self.audio_pattern;
+        snapshot.pitch = self.pitch;
+        snapshot.rng_state = self.rng.state();
+        snapshot.ram = self.ram.memory.clone();
+    }
+
+    pub(crate) fn load_state(&mut self, snapshot: &Snapshot) {
+        self.quirks = Quirks::from_bits(snapshot.quirks);
+        self.pc = snapshot.pc as usize;
+        self.i = snapshot.i as usize;
+        self.v = snapshot.v;
+        self.stack = snapshot
+            .stack
+            .iter()
+            .map(|address| *address as usize)
+            .collect();
+        self.delay_timer = snapshot.delay_timer;
+        self.sound_timer = snapshot.sound_timer;
+        self.exited = snapshot.exited;
+        self.waiting_for_key = snapshot.waiting_for_key;
+        self.key_down = snapshot.key_down;
+        self.rpl = snapshot.rpl;
+        self.audio_pattern = snapshot.audio_pattern;
+        self.pitch = snapshot.pitch;
+        self.rng.set_state(snapshot.rng_state);
+        self.ram.memory = snapshot.ram.clone();
+    }
+
+    pub fn audio_pattern(&self) -> &[u8; 16] {
+        &self.audio_pattern
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    // Playback rate of the audio pattern in bits per second: 4000 * 2^((pitch - 64) / 48)
+    pub fn audio_playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    // Skip the next instruction, which is 4 bytes long if it's the XO-CHIP F000 NNNN long load
+    fn skip_next(&mut self) {
+        let next_op = self.fetch_op_at(self.pc + 2).ok();
+        if self.quirks.xo_chip && next_op == Some(0xF000) {
+            self.pc += 6;
+        } else {
+            self.pc += 4;
+        }
+    }
+
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    // True while halted on FX0A, waiting for a key to be pressed and released
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.waiting_for_key
+    }
+
+    pub fn rpl_flags(&self) -> &[u8; 16] {
+        &self.rpl
+    }
+
+    pub fn set_rpl_flags(&mut self, flags: [u8; 16]) {
+        self.rpl = flags;
+    }
+
+    // 00CN: Scroll the display down by n pixels
+    fn op_00cn(&mut self, bus: &mut Bus, n: u8) {
+        bus.display.scroll_down(n as usize);
+        self.draw_flag = true;
+        self.pc += 2;
+    }
+
+    // 00DN: Scroll the display up by n pixels
+    fn op_00dn(&mut self, bus: &mut Bus, n: u8) {
+        bus.display.scroll_up(n as usize);
+        self.draw_flag = true;
+        self.pc += 2;
+    }
+
     fn op_00e0(&mut self, bus: &mut Bus) {
         bus.display.clear();
         self.pc += 2;
     }
 
     // Return from subroutine
-    fn op_00ee(&mut self) {
-        let address = self.stack.pop().unwrap();
+    fn op_00ee(&mut self) -> Result<()> {
+        let address = self
+            .stack
+            .pop()
+            .ok_or(Error::StackUnderflow { addr: self.pc })?;
         self.pc = address;
+        Ok(())
+    }
+
+    // 00FB: Scroll the display right by 4 pixels
+    fn op_00fb(&mut self, bus: &mut Bus) {
+        bus.display.scroll_right(4);
+        self.draw_flag = true;
+        self.pc += 2;
+    }
+
+    // 00FC: Scroll the display left by 4 pixels
+    fn op_00fc(&mut self, bus: &mut Bus) {
+        bus.display.scroll_left(4);
+        self.draw_flag = true;
+        self.pc += 2;
+    }
+
+    // 00FD: Exit the interpreter, the CPU stops executing instructions
+    fn op_00fd(&mut self) {
+        self.exited = true;
+    }
+
+    // 00FE: Disable high resolution mode (64x32)
+    fn op_00fe(&mut self, bus: &mut Bus) {
+        bus.display.set_hires(false);
+        self.draw_flag = true;
+        self.pc += 2;
+    }
+
+    // 00FF: Enable high resolution mode (128x64)
+    fn op_00ff(&mut self, bus: &mut Bus) {
+        bus.display.set_hires(true);
+        self.draw_flag = true;
+        self.pc += 2;
     }
 
     // fn op_0nnn(&mut self, nnn: u16) {
@@ -145,15 +377,19 @@ impl Cpu {
     }
 
     // The interpreter increments the stack pointer, then puts the current PC on the top of the stack. The PC is then set to nnn.
-    fn op_2nnn(&mut self, nnn: u16) {
+    fn op_2nnn(&mut self, nnn: u16) -> Result<()> {
+        if self.stack.len() == STACK_SIZE {
+            return Err(Error::StackOverflow { addr: self.pc });
+        }
         self.stack.push(self.pc + 2);
         self.pc = nnn as usize;
+        Ok(())
     }
 
     //  The interpreter compares register Vx to kk, and if they are equal, increments the program counter by 2.
     fn op_3xkk(&mut self, x: usize, kk: u8) {
         if self.v[x] == kk {
-            self.pc += 4;
+            self.skip_next();
         } else {
             self.pc += 2;
         }
@@ -162,7 +398,7 @@ impl Cpu {
     //  The interpreter compares register Vx to kk, and if they are not equal, increments the program counter by 2.
     fn op_4xkk(&mut self, x: usize, kk: u8) {
         if self.v[x] != kk {
-            self.pc += 4;
+            self.skip_next();
         } else {
             self.pc += 2;
         }
@@ -171,12 +407,39 @@ impl Cpu {
     //  The interpreter compares register Vx to register Vy, and if they are equal, increments the program counter by 2.
     fn op_5xy0(&mut self, x: usize, y: usize) {
         if self.v[x] == self.v[y] {
-            self.pc += 4;
+            self.skip_next();
         } else {
             self.pc += 2;
         }
     }
 
+    // 5xy2: Store registers Vx through Vy in memory starting at location I, I is left unchanged.
+    // The range can be ascending or descending.
+    fn op_5xy2(&mut self, x: usize, y: usize) -> Result<()> {
+        for (offset, idx) in Self::register_range(x, y).enumerate() {
+            self.ram.write_byte(self.i + offset, self.v[idx])?;
+        }
+        self.pc += 2;
+        Ok(())
+    }
+
+    // 5xy3: Read registers Vx through Vy from memory starting at location I, I is left unchanged.
+    fn op_5xy3(&mut self, x: usize, y: usize) -> Result<()> {
+        for (offset, idx) in Self::register_range(x, y).enumerate() {
+            self.v[idx] = self.ram.read_byte(self.i + offset)?;
+        }
+        self.pc += 2;
+        Ok(())
+    }
+
+    fn register_range(x: usize, y: usize) -> Box<dyn Iterator<Item = usize>> {
+        if x <= y {
+            Box::new(x..=y)
+        } else {
+            Box::new((y..=x).rev())
+        }
+    }
+
     // The interpreter puts the value kk into register Vx.
     fn op_6xkk(&mut self, x: usize, kk: u8) {
         self.v[x] = kk;
@@ -199,21 +462,41 @@ impl Cpu {
     // Performs a bitwise OR on the values of Vx and Vy, then stores the result in Vx. A bitwise OR compares the corrseponding bits from two values, and if either bit is 1, then the same bit in the result is also 1. Otherwise, it is 0.
     fn op_8xy1(&mut self, x: usize, y: usize) {
         self.v[x] |= self.v[y];
+        self.reset_vf();
         self.pc += 2;
     }
 
     // Performs a bitwise AND on the values of Vx and Vy, then stores the result in Vx. A bitwise AND compares the corrseponding bits from two values, and if both bits are 1, then the same bit in the result is also 1. Otherwise, it is 0.
     fn op_8xy2(&mut self, x: usize, y: usize) {
         self.v[x] &= self.v[y];
+        self.reset_vf();
         self.pc += 2;
     }
 
     // Performs a bitwise exclusive OR on the values of Vx and Vy, then stores the result in Vx. An exclusive OR compares the corrseponding bits from two values, and if the bits are not both the same, then the corresponding bit in the result is set to 1. Otherwise, it is 0.
     fn op_8xy3(&mut self, x: usize, y: usize) {
         self.v[x] ^= self.v[y];
+        self.reset_vf();
         self.pc += 2;
     }
 
+    // VF reset quirk: the logic operations of the COSMAC VIP clobber VF
+    fn reset_vf(&mut self) {
+        if self.quirks.vf_reset {
+            self.v[0xf] = 0;
+        }
+    }
+
+    // Shift quirk: SHR/SHL shift Vy like the original COSMAC VIP (`shift_uses_vy`),
+    // or Vx itself like CHIP-48 and SUPER-CHIP
+    fn shift_source(&self, x: usize, y: usize) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.v[y]
+        } else {
+            self.v[x]
+        }
+    }
+
     //  The values of Vx and Vy are added together. If the result is greater than 8 bits (i.e., > 255,) VF is set to 1, otherwise 0. Only the lowest 8 bits of the result are kept, and stored in Vx.
     fn op_8xy4(&mut self, x: usize, y: usize) {
         let result = self.v[x] as u16 + self.v[y] as u16;
@@ -231,9 +514,10 @@ impl Cpu {
     }
 
     //  If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0. Then Vx is divided by 2.
-    fn op_8xy6(&mut self, x: usize) {
-        self.v[0xf] = x as u8 & 0x1;
-        self.v[x] >>= 1;
+    fn op_8xy6(&mut self, x: usize, y: usize) {
+        let source = self.shift_source(x, y);
+        self.v[x] = source >> 1;
+        self.v[0xf] = source & 0x1;
         self.pc += 2;
     }
 
@@ -245,9 +529,10 @@ impl Cpu {
     }
 
     // If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0. Then Vx is multiplied by 2.
-    fn op_8xye(&mut self, x: usize) {
-        self.v[0xf] = (self.v[x] & 0b10000000) >> 7;
-        self.v[x] <<= 1;
+    fn op_8xye(&mut self, x: usize, y: usize) {
+        let source = self.shift_source(x, y);
+        self.v[x] = source << 1;
+        self.v[0xf] = (source & 0b10000000) >> 7;
         self.pc += 2;
     }
 
@@ -255,7 +540,7 @@ impl Cpu {
     // The values of Vx and Vy are compared, and if they are not equal, the program counter is increased by 2.
     fn op_9xy0(&mut self, x: usize, y: usize) {
         if self.v[x] != self.v[y] {
-            self.pc += 4;
+            self.skip_next();
         } else {
             self.pc += 2;
         }
@@ -268,15 +553,25 @@ impl Cpu {
     }
 
     // Bnnn: Jump to location nnn + V0. The program counter is set to nnn plus the value of V0.
+    // With the jump quirk (CHIP-48, SUPER-CHIP) this becomes Bxnn: jump to xnn + Vx.
     fn op_bnnn(&mut self, nnn: u16) {
-        self.pc = nnn as usize + self.v[0] as usize;
+        let offset = if self.quirks.jump_uses_vx {
+            self.v[((nnn & 0x0F00) >> 8) as usize]
+        } else {
+            self.v[0]
+        };
+        self.pc = nnn as usize + offset as usize;
     }
 
     // Cxkk: Set Vx = random byte AND kk.
     // The interpreter generates a random number from 0 to 255, which is then ANDed with the value kk. The results are stored in Vx. See instruction 8xy2 for more information on AND.
     fn op_cxkk(&mut self, x: usize, kk: u8) {
-        let mut rng = rand::thread_rng();
-        self.v[x] = rng.gen::<u8>() & kk;
+        let random = if self.quirks.vip_rng {
+            self.rng.next_vip_byte(&self.ram.memory[..0x100])
+        } else {
+            self.rng.next_byte()
+        };
+        self.v[x] = random & kk;
         self.pc += 2;
     }
 
@@ -285,40 +580,80 @@ impl Cpu {
     // These bytes are then displayed as sprites on screen at coordinates (Vx, Vy).
     // Sprites are XORed onto the existing screen. If this causes any pixels to be erased, VF is set to 1, otherwise it is set to 0.
     // If the sprite is positioned so part of it is outside the coordinates of the display, it wraps around to the opposite side of the screen. See instruction 8xy3 for more information on XOR, and section 2.4, Display, for more information on the Chip-8 screen and sprites.
-    fn op_dxyn(&mut self, bus: &mut Bus, x: usize, y: usize, n: u8) {
-        self.v[0xF] = 0;
-        let sprite = self.ram.read_bytes(self.i, self.i + n as usize);
+    fn op_dxyn(&mut self, bus: &mut Bus, x: usize, y: usize, n: u8) -> Result<()> {
+        let len = n as usize * bus.display.plane_count();
+        let sprite = self.ram.read_bytes(self.i, self.i + len)?;
+
+        let collision = bus.display.draw(
+            self.v[x] as usize,
+            self.v[y] as usize,
+            sprite,
+            self.quirks.clip_sprites,
+        );
+        self.v[0xF] = if collision { 1 } else { 0 };
+        self.draw_flag = true;
+        self.pc += 2;
+        Ok(())
+    }
+
+    // Dxy0: Display a 16x16 sprite (32 bytes) starting at memory location I at (Vx, Vy), set VF = collision.
+    fn op_dxy0(&mut self, bus: &mut Bus, x: usize, y: usize) -> Result<()> {
+        let len = 32 * bus.display.plane_count();
+        let sprite = self.ram.read_bytes(self.i, self.i + len)?;
 
-        let collision = bus
-            .display
-            .draw(self.v[x] as usize, self.v[y] as usize, sprite);
+        let collision = bus.display.draw_large(
+            self.v[x] as usize,
+            self.v[y] as usize,
+            sprite,
+            self.quirks.clip_sprites,
+        );
         self.v[0xF] = if collision { 1 } else { 0 };
         self.draw_flag = true;
         self.pc += 2;
+        Ok(())
     }
 
     //  ExA1: Skip next instruction if key with the value of Vx is NOT pressed.
-    fn op_exa1(&mut self, x: usize, bus: &mut Bus) {
-        let key = self.v[x];
+    fn op_exa1(&mut self, x: usize, bus: &Bus) {
+        let key = self.v[x] & 0xF;
         if !bus.is_key_pressed(key) {
-            self.pc += 4;
+            self.skip_next();
         } else {
-            bus.set_key_pressed(None);
             self.pc += 2;
         }
     }
 
     // Skip next instruction if key with the value of Vx is pressed.
-    fn op_ex9e(&mut self, x: usize, bus: &mut Bus) {
-        let key = self.v[x];
+    fn op_ex9e(&mut self, x: usize, bus: &Bus) {
+        let key = self.v[x] & 0xF;
         if bus.is_key_pressed(key) {
-            self.pc += 4;
+            self.skip_next();
         } else {
-            bus.set_key_pressed(None);
             self.pc += 2;
         }
     }
 
+    // F000 NNNN: Set I to the 16-bit address stored in the two bytes following the opcode.
+    fn op_f000(&mut self) -> Result<()> {
+        self.i = self.fetch_op_at(self.pc + 2)? as usize;
+        self.pc += 4;
+        Ok(())
+    }
+
+    // Fn01: Select the drawing planes, n is a bitmask (1: first plane, 2: second plane, 3: both).
+    fn op_fn01(&mut self, bus: &mut Bus, n: usize) {
+        bus.display.select_planes(n as u8);
+        self.pc += 2;
+    }
+
+    // F002: Load the 16 bytes starting at I into the audio pattern buffer.
+    fn op_f002(&mut self) -> Result<()> {
+        let pattern = self.ram.read_bytes(self.i, self.i + 16)?;
+        self.audio_pattern.copy_from_slice(pattern);
+        self.pc += 2;
+        Ok(())
+    }
+
     // The value of DT is placed into Vx.
     fn op_fx07(&mut self, x: usize) {
         self.v[x] = self.delay_timer;
@@ -327,11 +662,21 @@ impl Cpu {
 
     //  Fx0A: Wait for a key press, store the value of the key in Vx.
     // All execution stops until a key is pressed, then the value of that key is stored in Vx.
+    // As on the COSMAC VIP, the key is only stored once it has been released again.
+    // The PC stays on this instruction while waiting, so it is executed again every cycle
+    // and the timers keep running in the meantime.
     fn op_fx0a(&mut self, x: usize, bus: &Bus) {
-        if let Some(key_pressed) = bus.key_pressed {
-            self.v[x] = key_pressed;
+        self.waiting_for_key = true;
+        match self.key_down {
+            None => self.key_down = bus.first_key_pressed(),
+            Some(key) if !bus.is_key_pressed(key) => {
+                self.v[x] = key;
+                self.waiting_for_key = false;
+                self.key_down = None;
+                self.pc += 2;
+            }
+            Some(_) => {}
         }
-        self.pc += 2;
     }
 
     //  Fx15: Set delay timer = Vx. DT is set equal to the value of Vx.
@@ -359,32 +704,65 @@ impl Cpu {
         self.pc += 2;
     }
 
+    //  Fx30: Set I = location of the 8x10 sprite for digit Vx in the big font.
+    fn op_fx30(&mut self, x: usize) {
+        self.i = font::BIG_FONT_START + (self.v[x] as usize & 0xF) * 10;
+        self.pc += 2;
+    }
+
     //  Fx33: Store BCD representation of Vx in memory locations I, I+1, and I+2.
     // The interpreter takes the decimal value of Vx, and places the hundreds digit in memory at location in I, the tens digit at location I+1, and the ones digit at location I+2.
-    fn op_fx33(&mut self, x: usize) {
-        self.ram.write_byte(self.i, self.v[x] / 100);
-        self.ram.write_byte(self.i + 1, (self.v[x] % 100) / 10);
-        self.ram.write_byte(self.i + 2, self.v[x] % 10);
+    fn op_fx33(&mut self, x: usize) -> Result<()> {
+        self.ram.write_byte(self.i, self.v[x] / 100)?;
+        self.ram.write_byte(self.i + 1, (self.v[x] % 100) / 10)?;
+        self.ram.write_byte(self.i + 2, self.v[x] % 10)?;
+        self.pc += 2;
+        Ok(())
+    }
+
+    //  Fx3A: Set the pitch register to Vx, which sets the playback rate of the audio pattern.
+    fn op_fx3a(&mut self, x: usize) {
+        self.pitch = self.v[x];
         self.pc += 2;
     }
 
     //  Fx55: Store registers V0 through Vx in memory starting at location I.
     // The interpreter copies the values of registers V0 through Vx into memory, starting at the address in I.
-    fn op_fx55(&mut self, x: usize) {
+    fn op_fx55(&mut self, x: usize) -> Result<()> {
         for idx in 0..x + 1 {
             let val = self.v[idx];
-            self.ram.write_byte(self.i + idx, val);
+            self.ram.write_byte(self.i + idx, val)?;
+        }
+        if self.quirks.load_store_increments_i {
+            self.i += x + 1;
         }
         self.pc += 2;
+        Ok(())
     }
 
     //  Fx65: Read registers V0 through Vx from memory starting at location I.
     // The interpreter reads values from memory starting at location I into registers V0 through Vx.
-    fn op_fx65(&mut self, x: usize) {
+    fn op_fx65(&mut self, x: usize) -> Result<()> {
         for idx in 0..x + 1 {
-            let val = self.ram.read_byte(self.i + idx);
+            let val = self.ram.read_byte(self.i + idx)?;
             self.v[idx] = val;
         }
+        if self.quirks.load_store_increments_i {
+            self.i += x + 1;
+        }
+        self.pc += 2;
+        Ok(())
+    }
+
+    //  Fx75: Store registers V0 through Vx in the RPL user flags.
+    fn op_fx75(&mut self, x: usize) {
+        self.rpl[..=x].copy_from_slice(&self.v[..=x]);
+        self.pc += 2;
+    }
+
+    //  Fx85: Read registers V0 through Vx from the RPL user flags.
+    fn op_fx85(&mut self, x: usize) {
+        self.v[..=x].copy_from_slice(&self.rpl[..=x]);
         self.pc += 2;
     }
 }
@@ -398,3 +776,142 @@ impl fmt::Debug for Cpu {
         writeln!(f, "I: {:#X}", self.i)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::machine::Machine;
+    use crate::quirks::Quirks;
+    use crate::rng::Rng;
+
+    // Run `steps` instructions of `rom` with only `quirks` differing from the legacy defaults
+    fn run(quirks: Quirks, rom: &[u8], steps: usize) -> Machine {
+        let mut machine = Machine::with_quirks(rom, quirks).unwrap();
+        for _ in 0..steps {
+            machine.step().unwrap();
+        }
+        machine
+    }
+
+    #[test]
+    fn shift_uses_vy() {
+        // LD V1, 3  LD V2, 6  SHR V1, V2  SHL V3, V2
+        let rom = [0x61, 0x03, 0x62, 0x06, 0x81, 0x26, 0x83, 0x2E];
+        let vy = run(
+            Quirks {
+                shift_uses_vy: true,
+                ..Quirks::LEGACY
+            },
+            &rom,
+            4,
+        );
+        assert_eq!((vy.cpu().v[1], vy.cpu().v[3]), (3, 12));
+        let vx = run(Quirks::LEGACY, &rom, 4);
+        assert_eq!((vx.cpu().v[1], vx.cpu().v[3]), (1, 0));
+    }
+
+    #[test]
+    fn load_store_increments_i() {
+        // LD I, 0x300  LD [I], V1  LD V1, [I]
+        let rom = [0xA3, 0x00, 0xF1, 0x55, 0xF1, 0x65];
+        let quirks = Quirks {
+            load_store_increments_i: true,
+            ..Quirks::LEGACY
+        };
+        assert_eq!(run(quirks, &rom, 2).cpu().i, 0x302);
+        assert_eq!(run(quirks, &rom, 3).cpu().i, 0x304);
+        assert_eq!(run(Quirks::LEGACY, &rom, 3).cpu().i, 0x300);
+    }
+
+    #[test]
+    fn jump_uses_vx() {
+        // LD V0, 1  LD V2, 4  JP V0, 0x210
+        let rom = [0x60, 0x01, 0x62, 0x04, 0xB2, 0x10];
+        let quirks = Quirks {
+            jump_uses_vx: true,
+            ..Quirks::LEGACY
+        };
+        assert_eq!(run(quirks, &rom, 3).cpu().pc, 0x214);
+        assert_eq!(run(Quirks::LEGACY, &rom, 3).cpu().pc, 0x211);
+    }
+
+    #[test]
+    fn vf_reset() {
+        let quirks = Quirks {
+            vf_reset: true,
+            ..Quirks::LEGACY
+        };
+        for logic in [0x21, 0x22, 0x23] {
+            // LD VF, 5  OR/AND/XOR V1, V2
+            let rom = [0x6F, 0x05, 0x81, logic];
+            assert_eq!(run(quirks, &rom, 2).cpu().v[0xF], 0);
+            assert_eq!(run(Quirks::LEGACY, &rom, 2).cpu().v[0xF], 5);
+        }
+    }
+
+    #[test]
+    fn clip_sprites() {
+        // LD V0, 62  LD V1, 0  LD I, 0x208  DRW V0, V1, 1  with a row of 4 pixels at 0x208
+        let rom = [0x60, 0x3E, 0x61, 0x00, 0xA2, 0x08, 0xD0, 0x11, 0xF0];
+        let quirks = Quirks {
+            clip_sprites: true,
+            ..Quirks::LEGACY
+        };
+        let clipped = run(quirks, &rom, 4);
+        assert_eq!(clipped.framebuffer()[..2], [0, 0]);
+        assert_eq!(clipped.framebuffer()[62..64], [1, 1]);
+        let wrapped = run(Quirks::LEGACY, &rom, 4);
+        assert_eq!(wrapped.framebuffer()[..2], [1, 1]);
+    }
+
+    #[test]
+    fn display_wait() {
+        // LD I, 0x208  DRW V0, V0, 1  ADD V1, 1  JP 0x202
+        let rom = [0xA2, 0x08, 0xD0, 0x01, 0x71, 0x01, 0x12, 0x02, 0x80];
+        let quirks = Quirks {
+            display_wait: true,
+            ..Quirks::LEGACY
+        };
+        let mut waiting = Machine::with_quirks(&rom, quirks).unwrap();
+        waiting.run_frame().unwrap();
+        assert_eq!(waiting.cpu().v[1], 0);
+        let mut running = Machine::with_quirks(&rom, Quirks::LEGACY).unwrap();
+        running.run_frame().unwrap();
+        assert!(running.cpu().v[1] > 1);
+    }
+
+    #[test]
+    fn xo_chip() {
+        // LD I, LONG 0x1234
+        let rom = [0xF0, 0x00, 0x12, 0x34];
+        let quirks = Quirks {
+            xo_chip: true,
+            ..Quirks::LEGACY
+        };
+        let machine = run(quirks, &rom, 1);
+        assert_eq!((machine.cpu().i, machine.cpu().pc), (0x1234, 0x204));
+        let mut machine = Machine::with_quirks(&rom, Quirks::LEGACY).unwrap();
+        assert!(machine.step().is_err());
+    }
+
+    #[test]
+    fn vip_rng() {
+        // RND V1, 0xFF
+        let rom = [0xC1, 0xFF];
+        let quirks = Quirks {
+            vip_rng: true,
+            ..Quirks::LEGACY
+        };
+        for (quirks, vip) in [(quirks, true), (Quirks::LEGACY, false)] {
+            let mut machine = Machine::with_quirks(&rom, quirks).unwrap();
+            machine.set_seed(7);
+            let mut rng = Rng::new(7);
+            let expected = if vip {
+                rng.next_vip_byte(&machine.cpu().ram.memory[..0x100])
+            } else {
+                rng.next_byte()
+            };
+            machine.step().unwrap();
+            assert_eq!(machine.cpu().v[1], expected);
+        }
+    }
+}
@@ -0,0 +1,488 @@
+// Debugger:
+// decides, instruction by instruction, when a running machine has to stop.
+// Execution is paused on breakpoints (PC values), after a single step,
+// when a stepped-over subroutine returns or when a given address is reached,
+// when a RAM watchpoint is hit (see `Ram`) or when a condition becomes true.
+// Frontends drive it with `Command`s and show the machine state at each stop.
+
+use std::collections::BTreeSet;
+use std::fmt::{self, Write};
+
+use crate::condition::Condition;
+use crate::cpu::Cpu;
+use crate::disassembler::Disassembler;
+use crate::error::Result;
+use crate::machine::Machine;
+use crate::ram::{Access, WatchHit, Watchpoint};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Paused,
+    Running,
+    Step,
+    // Stop once the stack is back to `depth` with the PC on the return address
+    StepOver { return_pc: usize, depth: usize },
+    RunTo(usize),
+}
+
+// Why execution stopped, other than completing a step
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stop {
+    Breakpoint(usize),
+    // `pc` is the address of the instruction which made the access
+    Watchpoint { hit: WatchHit, pc: usize },
+    Condition(String),
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Breakpoint(address) => write!(f, "Breakpoint at {:#05X}", address),
+            Stop::Watchpoint { hit, pc } => {
+                let access = match hit.access {
+                    Access::Write => "Write of",
+                    _ => "Read of",
+                };
+                write!(
+                    f,
+                    "Watchpoint: {} {:#04X} at {:#05X} by the instruction at {:#05X}",
+                    access, hit.value, hit.address, pc
+                )
+            }
+            Stop::Condition(condition) => write!(f, "Condition became true: {}", condition),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Continue,
+    Step,
+    Next,
+    RunTo(usize),
+    Break(usize),
+    Delete(usize),
+    Watch(Watchpoint),
+    Unwatch(usize),
+    When(Condition),
+    Unwhen(usize),
+    Breakpoints,
+    Registers,
+    Stack,
+    Disassemble(Option<usize>),
+    Help,
+    Quit,
+}
+
+pub const HELP: &str = "\
+c, continue        resume execution
+s, step            execute a single instruction
+n, next            step over subroutine calls
+r, run <addr>      run until the PC reaches addr
+b, break <addr>    set a breakpoint at addr
+d, delete <addr>   remove the breakpoint at addr
+watch <addr> [end] stop when memory from addr to end is written
+rwatch <addr> [end] stop when it is read, awatch on any access
+unwatch <addr>     remove the watchpoints starting at addr
+when <expr>        stop when the expression becomes true, e.g. V3 == 0x10 && I > 0x300
+unwhen <n>         remove the condition numbered n in the list
+l, list            list breakpoints, watchpoints and conditions
+regs               show registers and timers
+stack              show the call stack
+dis [addr]         disassemble from addr (defaults to PC)
+q, quit            stop the emulator
+Addresses are hexadecimal, with or without a 0x prefix.
+Expressions compare V0-VF, I, PC, DT, ST, SP and memory bytes [addr]
+with == != < <= > >= && || ! and parentheses, numbers are decimal or 0x hex.
+An empty line repeats the previous command.";
+
+impl Command {
+    pub fn parse(line: &str) -> std::result::Result<Command, String> {
+        let line = line.trim();
+        let (name, rest) = match line.find(char::is_whitespace) {
+            Some(idx) => (&line[..idx], line[idx..].trim()),
+            None => (line, ""),
+        };
+
+        // Conditions take the whole rest of the line
+        match name {
+            "when" => return Ok(Command::When(Condition::parse(rest)?)),
+            "unwhen" => {
+                return rest
+                    .parse()
+                    .map(Command::Unwhen)
+                    .map_err(|_| "'unwhen' needs a condition number".to_string())
+            }
+            _ => {}
+        }
+
+        let addresses = rest
+            .split_whitespace()
+            .map(parse_address)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let max_addresses = match name {
+            "watch" | "rwatch" | "awatch" => 2,
+            "r" | "run" | "b" | "break" | "d" | "delete" | "unwatch" | "dis" => 1,
+            _ => 0,
+        };
+        if addresses.len() > max_addresses {
+            return Err(format!("too many arguments for '{}'", name));
+        }
+        let required = || {
+            addresses
+                .first()
+                .copied()
+                .ok_or_else(|| format!("'{}' needs an address", name))
+        };
+        let watch = |access| {
+            let start = required()?;
+            let end = addresses.get(1).copied().unwrap_or(start);
+            if end < start {
+                return Err("the end of the range is before its start".to_string());
+            }
+            Ok(Command::Watch(Watchpoint { start, end, access }))
+        };
+
+        match name {
+            "c" | "continue" => Ok(Command::Continue),
+            "s" | "step" => Ok(Command::Step),
+            "n" | "next" => Ok(Command::Next),
+            "r" | "run" => Ok(Command::RunTo(required()?)),
+            "b" | "break" => Ok(Command::Break(required()?)),
+            "d" | "delete" => Ok(Command::Delete(required()?)),
+            "watch" => watch(Access::Write),
+            "rwatch" => watch(Access::Read),
+            "awatch" => watch(Access::ReadWrite),
+            "unwatch" => Ok(Command::Unwatch(required()?)),
+            "l" | "list" => Ok(Command::Breakpoints),
+            "regs" => Ok(Command::Registers),
+            "stack" => Ok(Command::Stack),
+            "dis" => Ok(Command::Disassemble(addresses.first().copied())),
+            "h" | "help" => Ok(Command::Help),
+            "q" | "quit" => Ok(Command::Quit),
+            _ => Err(format!("unknown command '{}', try 'help'", name)),
+        }
+    }
+}
+
+fn parse_address(word: &str) -> std::result::Result<usize, String> {
+    let digits = word.trim_start_matches("0x").trim_start_matches("0X");
+    usize::from_str_radix(digits, 16).map_err(|_| format!("invalid address '{}'", word))
+}
+
+pub struct Debugger {
+    breakpoints: BTreeSet<usize>,
+    // Conditions with their value before the last instruction, they stop execution when it turns true
+    conditions: Vec<(Condition, bool)>,
+    state: State,
+    stop: Option<Stop>,
+    // The instruction execution resumes on never stops it again
+    resuming: bool,
+    // Address of the last instruction executed, which made any watched access
+    last_pc: usize,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    // Starts paused, so breakpoints can be set before the program runs
+    pub fn new() -> Self {
+        Self {
+            breakpoints: BTreeSet::new(),
+            conditions: Vec::new(),
+            state: State::Paused,
+            stop: None,
+            resuming: false,
+            last_pc: 0,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state == State::Paused
+    }
+
+    pub fn pause(&mut self) {
+        self.state = State::Paused;
+    }
+
+    // Reason of the last stop, if it wasn't the end of a step
+    pub fn take_stop(&mut self) -> Option<Stop> {
+        self.stop.take()
+    }
+
+    pub fn resume(&mut self) {
+        self.start(State::Running);
+    }
+
+    pub fn step(&mut self) {
+        self.start(State::Step);
+    }
+
+    // Like `step`, but a CALL runs the whole subroutine until it returns
+    pub fn step_over(&mut self, machine: &Machine) {
+        let cpu = machine.cpu();
+        match cpu.ram.peek_byte(cpu.pc) {
+            Ok(byte) if byte & 0xF0 == 0x20 => self.start(State::StepOver {
+                return_pc: cpu.pc + 2,
+                depth: cpu.stack().len(),
+            }),
+            _ => self.step(),
+        }
+    }
+
+    pub fn run_to(&mut self, address: usize) {
+        self.start(State::RunTo(address));
+    }
+
+    fn start(&mut self, state: State) {
+        self.state = state;
+        self.resuming = true;
+    }
+
+    // Returns true if the breakpoint was new
+    pub fn add_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    // Returns true if there was a breakpoint at the address
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = &usize> {
+        self.breakpoints.iter()
+    }
+
+    // Conditions only stop execution once they turn from false to true
+    pub fn add_condition(&mut self, condition: Condition, machine: &Machine) {
+        let value = condition.is_true(machine.cpu());
+        self.conditions.push((condition, value));
+    }
+
+    pub fn remove_condition(&mut self, idx: usize) -> Option<Condition> {
+        if idx < self.conditions.len() {
+            Some(self.conditions.remove(idx).0)
+        } else {
+            None
+        }
+    }
+
+    pub fn conditions(&self) -> impl Iterator<Item = &Condition> {
+        self.conditions.iter().map(|(condition, _)| condition)
+    }
+
+    // Run the machine for (the rest of) a frame, unless paused
+    // Execution errors pause the debugger, the PC is left on the faulty instruction
+    pub fn run_frame(&mut self, machine: &mut Machine) -> Result<()> {
+        if self.is_paused() {
+            return Ok(());
+        }
+        let result = machine.run_until(|cpu| self.should_stop(cpu));
+        if result.is_err() {
+            self.pause();
+        }
+        result.map(|_| ())
+    }
+
+    fn should_stop(&mut self, cpu: &Cpu) -> bool {
+        let hit = cpu.ram.take_watch_hit();
+        let mut became_true = None;
+        for (condition, value) in self.conditions.iter_mut() {
+            let previous = std::mem::replace(value, condition.is_true(cpu));
+            if *value && !previous && became_true.is_none() {
+                became_true = Some(condition.to_string());
+            }
+        }
+        let last_pc = std::mem::replace(&mut self.last_pc, cpu.pc);
+        if std::mem::replace(&mut self.resuming, false) {
+            return false;
+        }
+
+        self.stop = if let Some(hit) = hit {
+            Some(Stop::Watchpoint { hit, pc: last_pc })
+        } else if let Some(condition) = became_true {
+            Some(Stop::Condition(condition))
+        } else if self.breakpoints.contains(&cpu.pc) {
+            Some(Stop::Breakpoint(cpu.pc))
+        } else {
+            None
+        };
+        let stop = self.stop.is_some()
+            || match self.state {
+                State::Paused | State::Step => true,
+                State::Running => false,
+                State::StepOver { return_pc, depth } => {
+                    cpu.pc == return_pc && cpu.stack().len() == depth
+                }
+                State::RunTo(address) => cpu.pc == address,
+            };
+        if stop {
+            self.pause();
+        }
+        stop
+    }
+}
+
+// Instruction at the address and its size in bytes (XO-CHIP F000 NNNN takes 4)
+pub fn disassemble(machine: &Machine, address: usize) -> (String, usize) {
+    let ram = &machine.cpu().ram;
+    let word = |address| {
+        let hi_byte = ram.peek_byte(address).ok()? as u16;
+        let lo_byte = ram.peek_byte(address + 1).ok()? as u16;
+        Some(hi_byte << 8 | lo_byte)
+    };
+    match word(address) {
+        Some(0xF000) => match word(address + 2) {
+            Some(long) => (
+                format!("{:04X}{:04X}  LD I, LONG {}", 0xF000, long, long),
+                4,
+            ),
+            None => ("F000".to_string(), 2),
+        },
+        Some(opcode) => (
+            format!("{:04X}      {}", opcode, Disassembler::decode_op(opcode)),
+            2,
+        ),
+        None => ("????".to_string(), 2),
+    }
+}
+
+// Listing of `count` instructions starting at the address, the current one marked with '>'
+pub fn listing(machine: &Machine, mut address: usize, count: usize) -> String {
+    let mut out = String::new();
+    for _ in 0..count {
+        let (instruction, size) = disassemble(machine, address);
+        let marker = if address == machine.cpu().pc {
+            '>'
+        } else {
+            ' '
+        };
+        let _ = writeln!(out, "{} {:#05X}  {}", marker, address, instruction);
+        address += size;
+    }
+    out
+}
+
+pub fn registers(machine: &Machine) -> String {
+    let cpu = machine.cpu();
+    let mut out = String::new();
+    for (idx, reg) in cpu.v.iter().enumerate() {
+        let separator = if idx % 8 == 7 { '\n' } else { ' ' };
+        let _ = write!(out, "V{:X}={:02X}{}", idx, reg, separator);
+    }
+    let _ = write!(
+        out,
+        "PC={:#05X} I={:#05X} DT={:02X} ST={:02X} SP={}",
+        cpu.pc,
+        cpu.i,
+        cpu.delay_timer(),
+        cpu.sound_timer(),
+        cpu.stack().len()
+    );
+    if cpu.is_waiting_for_key() {
+        out.push_str(" (waiting for key)");
+    }
+    out
+}
+
+pub fn stack(machine: &Machine) -> String {
+    let stack = machine.cpu().stack();
+    if stack.is_empty() {
+        return "Stack is empty".to_string();
+    }
+    let mut out = String::new();
+    for (depth, address) in stack.iter().enumerate().rev() {
+        let _ = writeln!(out, "#{:<2} {:#05X}", depth, address);
+    }
+    out.pop();
+    out
+}
+
+// Everything shown when execution stops: registers, stack and the current instruction
+pub fn status(machine: &Machine) -> String {
+    let cpu = machine.cpu();
+    let (instruction, _) = disassemble(machine, cpu.pc);
+    let mut out = registers(machine);
+    let _ = write!(out, "\nStack: ");
+    for address in cpu.stack().iter().rev() {
+        let _ = write!(out, "{:#05X} ", address);
+    }
+    let _ = write!(out, "\n> {:#05X}  {}", cpu.pc, instruction);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Run `rom` under the debugger for a frame or until it stops, with I = 0x300 and V1 = 123,
+    // then loop forever
+    fn run(rom: &[u8], watchpoint: Option<Watchpoint>) -> (Machine, Option<Stop>) {
+        let mut program = vec![0xA3, 0x00, 0x61, 123];
+        program.extend_from_slice(rom);
+        let end = 0x200 + program.len() as u16;
+        program.extend_from_slice(&(0x1000 | end).to_be_bytes());
+        let mut machine = Machine::new(&program).unwrap();
+        if let Some(watchpoint) = watchpoint {
+            machine.add_watchpoint(watchpoint);
+        }
+        let mut debugger = Debugger::new();
+        debugger.resume();
+        debugger.run_frame(&mut machine).unwrap();
+        (machine, debugger.take_stop())
+    }
+
+    fn watch(start: usize, end: usize, access: Access) -> Option<Watchpoint> {
+        Some(Watchpoint { start, end, access })
+    }
+
+    fn hit(address: usize, access: Access, value: u8) -> Option<Stop> {
+        Some(Stop::Watchpoint {
+            hit: WatchHit {
+                address,
+                access,
+                value,
+            },
+            pc: 0x204,
+        })
+    }
+
+    #[test]
+    fn stops_on_watched_accesses() {
+        // LD [I], V1
+        let (_, stop) = run(&[0xF1, 0x55], watch(0x301, 0x301, Access::Write));
+        assert_eq!(stop, hit(0x301, Access::Write, 123));
+        // LD V1, [I]
+        let (_, stop) = run(&[0xF1, 0x65], watch(0x300, 0x30F, Access::Read));
+        assert_eq!(stop, hit(0x300, Access::Read, 0));
+        // LD B, V1
+        let (_, stop) = run(&[0xF1, 0x33], watch(0x302, 0x302, Access::ReadWrite));
+        assert_eq!(stop, hit(0x302, Access::Write, 3));
+        // DRW V0, V0, 4
+        let (_, stop) = run(&[0xD0, 0x04], watch(0x303, 0x303, Access::Read));
+        assert_eq!(stop, hit(0x303, Access::Read, 0));
+    }
+
+    #[test]
+    fn ignores_other_accesses() {
+        // A read of a write watchpoint, then outside of a read watchpoint
+        let (_, stop) = run(&[0xF1, 0x65], watch(0x300, 0x300, Access::Write));
+        assert_eq!(stop, None);
+        let (_, stop) = run(&[0xD0, 0x04], watch(0x304, 0x304, Access::Read));
+        assert_eq!(stop, None);
+    }
+
+    #[test]
+    fn doesnt_watch_without_watchpoints() {
+        // LD [I], V1  LD V1, [I]  LD B, V1  DRW V0, V0, 4
+        let rom = [0xF1, 0x55, 0xF1, 0x65, 0xF1, 0x33, 0xD0, 0x04];
+        let (machine, stop) = run(&rom, None);
+        assert_eq!(stop, None);
+        assert_eq!(machine.cpu().ram.take_watch_hit(), None);
+        assert_eq!(machine.frame_count(), 1);
+    }
+}
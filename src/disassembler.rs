@@ -1,4 +1,5 @@
-use crate::ram::Ram;
+use crate::error::Result;
+use crate::ram::{Ram, XO_RAM_SIZE};
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::Path;
@@ -12,35 +13,45 @@ pub struct Disassembler {
 }
 
 impl Disassembler {
-    pub fn new(rom_path: String) -> Self {
+    pub fn new(rom_path: String) -> Result<Self> {
         let mut rom_buffer = Vec::<u8>::new();
-        let mut file = File::open(&rom_path).expect("File not found");
+        let mut file = File::open(&rom_path)?;
 
-        if let Ok(bytes_read) = file.read_to_end(&mut rom_buffer) {
-            println!("{} bytes loaded", bytes_read);
-        } else {
-            println!("Error loading ROM");
-        };
+        let bytes_read = file.read_to_end(&mut rom_buffer)?;
+        println!("{} bytes loaded", bytes_read);
 
-        Self {
-            ram: Ram::new(&rom_buffer),
+        Ok(Self {
+            // Always use the XO-CHIP address space so larger ROMs can be disassembled
+            ram: Ram::with_size(&rom_buffer, XO_RAM_SIZE)?,
             rom_size: rom_buffer.len() + START_ROM,
             rom_path,
-        }
+        })
     }
 
     pub fn run(&self) {
         let mut opcode_buffer = Vec::new();
-        println!("Address  Opcode  Instruction");  
-        for idx in START_ROM..self.rom_size {
-            // Check opcodes only at even addresses to prevent overflow
-            // Possible problems since some ROMs include binary data at various addresses
-            if idx & 1 == 0 && idx + 1 < self.rom_size {
-                let opcode = self.fetch_op(idx);
-                let instruction = self.decode_op(opcode);
-                println!("[{}]    {:04x}    {}", idx, opcode, instruction);
+        println!("Address  Opcode  Instruction");
+        let mut idx = START_ROM;
+        // Check opcodes only at even addresses to prevent overflow
+        // Possible problems since some ROMs include binary data at various addresses
+        while idx + 1 < self.rom_size {
+            let opcode = self.fetch_op(idx);
+            // XO-CHIP F000 NNNN is the only 4-byte instruction
+            if opcode == 0xF000 && idx + 3 < self.rom_size {
+                let address = self.fetch_op(idx + 2);
+                let instruction = format!("LD I, LONG {}", address);
+                println!(
+                    "[{}]    {:04x}{:04x}    {}",
+                    idx, opcode, address, instruction
+                );
                 opcode_buffer.push(instruction);
+                idx += 4;
+                continue;
             }
+            let instruction = Self::decode_op(opcode);
+            println!("[{}]    {:04x}    {}", idx, opcode, instruction);
+            opcode_buffer.push(instruction);
+            idx += 2;
         }
         match self.write_file(opcode_buffer) {
             Ok(path) => println!("File disassembled: {}", path),
@@ -53,7 +64,7 @@ impl Disassembler {
         let path = Path::new(&file_name);
         let display = path.display();
 
-        let mut file = match File::create(&path) {
+        let mut file = match File::create(path) {
             Err(e) => panic!("Couldn't create {}: {}", display, e),
             Ok(file) => file,
         };
@@ -64,7 +75,7 @@ impl Disassembler {
 
     fn parse_path(&self) -> String {
         let file_name: Vec<_> = self.rom_path.split(".ch8").collect();
-        return format!("{}.chasm", file_name[0]);
+        format!("{}.chasm", file_name[0])
     }
 
     fn fetch_op(&self, idx: usize) -> u16 {
@@ -73,7 +84,9 @@ impl Disassembler {
         (hi_byte as u16) << 8 | lo_byte as u16
     }
 
-    fn decode_op(&self, opcode: u16) -> String {
+    // Mnemonic of a single 2-byte opcode, also used by the debugger
+    // Anything else is written as a raw word, so the output re-assembles to the same bytes
+    pub fn decode_op(opcode: u16) -> String {
         let nibbles = (
             ((opcode & 0xF000) >> 12) as u8,
             ((opcode & 0x0F00) >> 8) as u8,
@@ -83,20 +96,29 @@ impl Disassembler {
 
         let x = nibbles.1 as usize;
         let y = nibbles.2 as usize;
-        let n = nibbles.3 as u8;
+        let n = nibbles.3;
         let kk = (opcode & 0x00FF) as u8;
         let nnn = opcode & 0x0FFF;
         let result = match nibbles {
-            (0x00, _, _, _) => match kk {
-                0xE0 => String::from("CLS"), // 00E0 - CLS: Clear display
-                0xEE => String::from("RET"), // 00EE - RET : Return from subroutine
-                _ => format!("{:x}", opcode),
+            (0x00, _, _, _) => match nnn {
+                0x0C0..=0x0CF => format!("SCD {}", n), // 00CN - SCD nibble: Scroll display down n lines
+                0x0D0..=0x0DF => format!("SCU {}", n), // 00DN - SCU nibble: Scroll display up n lines
+                0x0E0 => String::from("CLS"),          // 00E0 - CLS: Clear display
+                0x0EE => String::from("RET"),          // 00EE - RET : Return from subroutine
+                0x0FB => String::from("SCR"),          // 00FB - SCR: Scroll display right 4 pixels
+                0x0FC => String::from("SCL"),          // 00FC - SCL: Scroll display left 4 pixels
+                0x0FD => String::from("EXIT"),         // 00FD - EXIT: Exit the interpreter
+                0x0FE => String::from("LOW"),          // 00FE - LOW: Switch to low resolution mode
+                0x0FF => String::from("HIGH"), // 00FF - HIGH: Switch to high resolution mode
+                _ => format!("{:#06x}", opcode),
             },
             (0x01, _, _, _) => format!("JP {}", nnn), // 1NNN - JP addr: Jump to location nnn.
             (0x02, _, _, _) => format!("CALL {}", nnn), // 2NNN - CALL addr: Call subroutine at nnn.
             (0x03, _, _, _) => format!("SE V{}, {}", x, kk), // 3XKK - SE Vx, byte: Skip next instruction if Vx = kk.
             (0x04, _, _, _) => format!("SNE V{}, {}", x, kk), // 4XKK - SNE Vx, byte: Skip next instruction if Vx != kk.
-            (0x05, _, _, _) => format!("SE V{}, V{}", x, y), // 5XY0 - SE Vx, Vy: Skip next instruction if Vx = Vy.
+            (0x05, _, _, 0x00) => format!("SE V{}, V{}", x, y), // 5XY0 - SE Vx, Vy: Skip next instruction if Vx = Vy.
+            (0x05, _, _, 0x02) => format!("SAVE V{}, V{}", x, y), // 5XY2 - SAVE Vx, Vy: Store registers Vx through Vy in memory starting at location I.
+            (0x05, _, _, 0x03) => format!("LOAD V{}, V{}", x, y), // 5XY3 - LOAD Vx, Vy: Read registers Vx through Vy from memory starting at location I.
             (0x06, _, _, _) => format!("LD V{}, {}", x, kk), // 6XKK - LD Vx, byte: Set Vx = kk.
             (0x07, _, _, _) => format!("ADD V{}, {}", x, kk), // 7XKK - ADD Vx, byte: Set Vx = Vx + kk.
             (0x08, _, _, _) => match n {
@@ -106,34 +128,42 @@ impl Disassembler {
                 0x03 => format!("XOR V{}, V{}", x, y), //  8XY3 - XOR Vx, Vy: Set Vx = Vx XOR Vy.
                 0x04 => format!("ADD V{}, V{}", x, y), //  8XY4 - ADD Vx, Vy: Set Vx = Vx + Vy, set VF = carry.
                 0x05 => format!("SUB V{}, V{}", x, y), //  8XY5 - SUB Vx, Vy: Set Vx = Vx - Vy, set VF = NOT borrow.
-                0x06 => format!("SHR V{}", x),         //  8XY6 - SHR Vx: Set Vx = Vx SHR 1.
-                0x07 => format!("SUBN V{} V{}", x, y), //  8XY7 - SUBN Vx, Vy: Set Vx = Vy - Vx, set VF = NOT borrow.
-                0x0E => format!("SHL V{}", x),         //  8XYE - SHL Vx: Set Vx = Vx SHL 1.
-                _ => format!("{:x}", opcode),
+                0x06 if x == y => format!("SHR V{}", x), //  8XY6 - SHR Vx {, Vy}: Set Vx = Vy SHR 1 like the original COSMAC VIP (`shift_uses_vy`), or Vx SHR 1 with the CHIP-48 shift quirk.
+                0x06 => format!("SHR V{}, V{}", x, y),
+                0x07 => format!("SUBN V{}, V{}", x, y), //  8XY7 - SUBN Vx, Vy: Set Vx = Vy - Vx, set VF = NOT borrow.
+                0x0E if x == y => format!("SHL V{}", x), //  8XYE - SHL Vx {, Vy}: Set Vx = Vy SHL 1 like the original COSMAC VIP (`shift_uses_vy`), or Vx SHL 1 with the CHIP-48 shift quirk.
+                0x0E => format!("SHL V{}, V{}", x, y),
+                _ => format!("{:#06x}", opcode),
             },
-            (0x09, _, _, _) => format!("SNE V{}, V{}", x, y), // 9XY0 - SNE Vx, Vy: Skip next instruction if Vx != Vy.
-            (0x0A, _, _, _) => format!("LD I, {}", nnn),      // ANNN - LD I, addr: Set I to NNN
+            (0x09, _, _, 0x00) => format!("SNE V{}, V{}", x, y), // 9XY0 - SNE Vx, Vy: Skip next instruction if Vx != Vy.
+            (0x0A, _, _, _) => format!("LD I, {}", nnn),         // ANNN - LD I, addr: Set I to NNN
             (0x0B, _, _, _) => format!("JP V0, {}", nnn), // BNNN - JP V0, addr: Jump to location nnn + V0.
             (0x0C, _, _, _) => format!("RND V{}, {}", x, kk), // CXKK - RND Vx, byte: Set Vx = random byte AND kk.
             (0x0D, _, _, _) => format!("DRW V{}, V{}, {}", x, y, n), // DXYN - DRW, Vx, Vy, nibble: Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
             (0x0E, _, _, _) => match kk {
                 0x9E => format!("SKP V{}", x), //  Ex9E - SKP Vx:  Skip next instruction if key with the value of Vx is pressed.
                 0xA1 => format!("SKNP V{}", x), //  EXA1 - SKNP Vx: Skip next instruction if key with the value of Vx is not pressed.
-                _ => format!("{:x}", opcode),
+                _ => format!("{:#06x}", opcode),
             },
             (0x0F, _, _, _) => match kk {
+                0x01 => format!("PLANE {}", x), //  FN01 - PLANE n: Select the drawing planes given by bitmask n.
+                0x02 if x == 0 => String::from("AUDIO"), //  F002 - AUDIO: Load 16 bytes starting at I into the audio pattern buffer.
                 0x07 => format!("LD V{}, DT", x), //  FX07 - LD Vx, DT: Set Vx = delay timer value. The value of DT is placed into Vx.
                 0x0A => format!("LD V{}, K", x), //  FX0A - LD Vx, K: Wait for a key press, store the value of the key in Vx.
                 0x15 => format!("LD DT, V{}", x), //  FX15 - LD DT, Vx: Set delay timer = Vx.
                 0x18 => format!("LD ST, V{}", x), //  FX18 - LD ST, Vx: Set sound timer = Vx.
                 0x1E => format!("ADD I, V{}", x), //  FX1E - ADD I, Vx: Set I = I + Vx.
                 0x29 => format!("LD F, V{}", x), //  FX29 - LD F, Vx: Set I = location of sprite for digit Vx.
+                0x30 => format!("LD HF, V{}", x), //  FX30 - LD HF, Vx: Set I = location of big sprite for digit Vx.
                 0x33 => format!("LD B, V{}", x), //  FX33 - LD B, Vx: Store BCD representation of Vx in memory locations I, I+1, and I+2.
+                0x3A => format!("PITCH V{}", x), //  FX3A - PITCH Vx: Set the audio pattern playback rate from Vx.
                 0x55 => format!("LD I, V{}", x), //  FX55 - LD [I], Vx: Store registers V0 through Vx in memory starting at location I.
                 0x65 => format!("LD V{}, I", x), //  FX65 - Ld Vx, [I]: Read registers V0 through Vx from memory starting at location I.
-                _ => format!("{:x}", opcode),
+                0x75 => format!("LD R, V{}", x), //  FX75 - LD R, Vx: Store registers V0 through Vx in the RPL user flags.
+                0x85 => format!("LD V{}, R", x), //  FX85 - LD Vx, R: Read registers V0 through Vx from the RPL user flags.
+                _ => format!("{:#06x}", opcode),
             },
-            _ => format!("{:x}", opcode),
+            _ => format!("{:#06x}", opcode),
         };
         result
     }
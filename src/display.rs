@@ -4,60 +4,233 @@
 // which are always 8 X N (N is pixel height).
 // Font set sprites: characters 0-9 and A-F
 // to be printed directly within 8x5 grid.
+//
+// SUPER-CHIP adds a 128x64 high resolution mode,
+// 16x16 sprites and scrolling of the whole screen.
+//
+// XO-CHIP adds a second bitplane: each pixel holds one bit per plane,
+// giving four colours. Drawing, clearing and scrolling only affect
+// the planes currently selected with FN01.
 
-const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+pub const PLANES: usize = 2;
+
+use crate::snapshot::Snapshot;
 
 pub struct Display {
-    frame_buffer: [u8; WIDTH * HEIGHT],
+    width: usize,
+    height: usize,
+    plane_mask: u8,
+    frame_buffer: Vec<u8>,
 }
 
 impl Display {
     pub fn new() -> Display {
         Display {
-            frame_buffer: [0; WIDTH * HEIGHT],
+            width: WIDTH,
+            height: HEIGHT,
+            plane_mask: 0x1,
+            frame_buffer: vec![0; WIDTH * HEIGHT],
         }
     }
 
     pub fn clear(&mut self) {
+        let mask = self.plane_mask;
         for pixel in self.frame_buffer.iter_mut() {
-            *pixel = 0;
+            *pixel &= !mask;
         }
     }
 
+    // FN01: Select the bitplanes affected by drawing, clearing and scrolling
+    pub fn select_planes(&mut self, mask: u8) {
+        self.plane_mask = mask & 0x3;
+    }
+
+    pub fn plane_mask(&self) -> u8 {
+        self.plane_mask
+    }
+
+    // Number of selected planes, sprites hold one set of rows per selected plane
+    pub fn plane_count(&self) -> usize {
+        self.plane_mask.count_ones() as usize
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.width == HIRES_WIDTH
+    }
+
+    // Switch between low (64x32) and high (128x64) resolution, clearing the screen
+    pub fn set_hires(&mut self, hires: bool) {
+        let (width, height) = if hires {
+            (HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            (WIDTH, HEIGHT)
+        };
+        self.width = width;
+        self.height = height;
+        self.frame_buffer = vec![0; width * height];
+    }
+
     pub fn get_index(&self, x: usize, y: usize) -> usize {
-        x + WIDTH * y
+        x + self.width * y
     }
 
     // Set/unset pixels in display_buffer, return true/false if collision detected
-    pub fn draw(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+    // The starting position always wraps around the screen, while the rest of the sprite
+    // is either clipped at the edges or wrapped around to the opposite side.
+    // With several planes selected, the sprite holds the rows for each plane one after the other
+    pub fn draw(&mut self, x: usize, y: usize, sprite: &[u8], clip: bool) -> bool {
         // Sprite height can be between 1 and 15 bytes, sprite width is 8 bits
         // Bit pattern shows which pixel to set/unset using XOR
         // loop by byte/row: 0..sprite.len()
         // loop by bit/col: 0..7
         // val = (row >> 7 - bit) & 0x01
-        // 00000000
-        // 0
         let mut collision = false;
+        for (plane, plane_sprite) in self.split_planes(sprite) {
+            let rows = plane_sprite.iter().map(|row| *row as u16);
+            collision |= self.draw_rows(x, y, rows, 8, plane, clip);
+        }
+        collision
+    }
+
+    // SUPER-CHIP 16x16 sprite: 32 bytes per plane, two bytes per row
+    pub fn draw_large(&mut self, x: usize, y: usize, sprite: &[u8], clip: bool) -> bool {
+        let mut collision = false;
+        for (plane, plane_sprite) in self.split_planes(sprite) {
+            let rows = plane_sprite
+                .chunks(2)
+                .map(|row| (row[0] as u16) << 8 | *row.get(1).unwrap_or(&0) as u16);
+            collision |= self.draw_rows(x, y, rows, 16, plane, clip);
+        }
+        collision
+    }
+
+    fn split_planes<'a>(&self, sprite: &'a [u8]) -> Vec<(usize, &'a [u8])> {
+        let count = self.plane_count().max(1);
+        let len = sprite.len() / count;
+        (0..PLANES)
+            .filter(|plane| self.plane_mask & (1 << plane) != 0)
+            .zip(sprite.chunks(len.max(1)))
+            .collect()
+    }
+
+    fn draw_rows<I>(
+        &mut self,
+        x: usize,
+        y: usize,
+        rows: I,
+        row_width: usize,
+        plane: usize,
+        clip: bool,
+    ) -> bool
+    where
+        I: Iterator<Item = u16>,
+    {
+        let mut collision = false;
+        let bit = 1 << plane;
 
-        for (i, row) in sprite.iter().enumerate() {
-            let y_coord = (y + i) % 32;
-            for col in 0..8 {
-                let val = *row >> (7 - col) & 0x01;
-                let x_coord = (x + col) % 64;
+        let (x, y) = (x % self.width, y % self.height);
+
+        for (i, row) in rows.enumerate() {
+            if clip && y + i >= self.height {
+                break;
+            }
+            let y_coord = (y + i) % self.height;
+            for col in 0..row_width {
+                if clip && x + col >= self.width {
+                    break;
+                }
+                let val = (row >> (row_width - 1 - col) & 0x01) as u8;
+                if val == 0 {
+                    continue;
+                }
+                let x_coord = (x + col) % self.width;
                 let offset = self.get_index(x_coord, y_coord);
-                let prev_val = self.frame_buffer[offset];
-                self.frame_buffer[offset] ^= val;
-                if prev_val == 1 && self.frame_buffer[offset] == 0 {
+                if self.frame_buffer[offset] & bit != 0 {
                     collision = true;
                 }
+                self.frame_buffer[offset] ^= bit;
             }
         }
 
         collision
     }
 
+    // 00CN: Scroll the display down by n pixels, the top rows are left blank
+    pub fn scroll_down(&mut self, n: usize) {
+        self.scroll(0, n as isize);
+    }
+
+    // 00DN: Scroll the display up by n pixels (XO-CHIP)
+    pub fn scroll_up(&mut self, n: usize) {
+        self.scroll(0, -(n as isize));
+    }
+
+    // 00FB: Scroll the display right by n pixels
+    pub fn scroll_right(&mut self, n: usize) {
+        self.scroll(n as isize, 0);
+    }
+
+    // 00FC: Scroll the display left by n pixels
+    pub fn scroll_left(&mut self, n: usize) {
+        self.scroll(-(n as isize), 0);
+    }
+
+    // Move the selected planes by (dx, dy), pixels scrolled in from the edges are blank
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let mask = self.plane_mask;
+        let previous = self.frame_buffer.clone();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let src_x = x as isize - dx;
+                let src_y = y as isize - dy;
+                let inside = src_x >= 0
+                    && src_y >= 0
+                    && (src_x as usize) < self.width
+                    && (src_y as usize) < self.height;
+                let scrolled = if inside {
+                    previous[self.get_index(src_x as usize, src_y as usize)] & mask
+                } else {
+                    0
+                };
+                let offset = self.get_index(x, y);
+                self.frame_buffer[offset] = (previous[offset] & !mask) | scrolled;
+            }
+        }
+    }
+
+    pub(crate) fn save_state(&self, snapshot: &mut Snapshot) {
+        snapshot.display_width = self.width as u16;
+        snapshot.display_height = self.height as u16;
+        snapshot.plane_mask = self.plane_mask;
+        snapshot.frame_buffer = self.frame_buffer.clone();
+    }
+
+    pub(crate) fn load_state(&mut self, snapshot: &Snapshot) {
+        self.width = snapshot.display_width as usize;
+        self.height = snapshot.display_height as usize;
+        self.plane_mask = snapshot.plane_mask;
+        self.frame_buffer = snapshot.frame_buffer.clone();
+    }
+
     pub fn get_frame_buffer(&self) -> &[u8] {
         &self.frame_buffer
     }
 }
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
+}
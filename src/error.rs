@@ -0,0 +1,73 @@
+// Errors raised while loading ROMs and executing instructions.
+// Embedding tools get these back from `Machine` instead of the
+// emulator crashing or silently skipping over faulty instructions.
+
+use std::{fmt, io};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    // The ROM doesn't fit in memory after the interpreter area (0x200)
+    RomTooLarge { size: usize, max: usize },
+    InvalidOpcode { addr: usize, opcode: u16 },
+    // RET with an empty stack
+    StackUnderflow { addr: usize },
+    // CALL with a full stack
+    StackOverflow { addr: usize },
+    // Fetch, load/store or sprite read past the end of memory
+    MemoryOutOfBounds { addr: usize },
+    // Save state which can't be decoded or doesn't fit this machine
+    InvalidSnapshot(String),
+    // Movie file which can't be decoded or was recorded with another ROM
+    InvalidMovie(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::RomTooLarge { size, max } => {
+                write!(
+                    f,
+                    "ROM is too large: {} bytes (maximum {} bytes)",
+                    size, max
+                )
+            }
+            Error::InvalidOpcode { addr, opcode } => {
+                write!(f, "Invalid opcode {:04X} at {:#05X}", opcode, addr)
+            }
+            Error::StackUnderflow { addr } => {
+                write!(
+                    f,
+                    "Stack underflow: return with empty stack at {:#05X}",
+                    addr
+                )
+            }
+            Error::StackOverflow { addr } => {
+                write!(f, "Stack overflow: call with full stack at {:#05X}", addr)
+            }
+            Error::MemoryOutOfBounds { addr } => {
+                write!(f, "Memory access out of bounds at {:#06X}", addr)
+            }
+            Error::InvalidSnapshot(reason) => write!(f, "Invalid save state: {}", reason),
+            Error::InvalidMovie(reason) => write!(f, "Invalid movie: {}", reason),
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
@@ -0,0 +1,641 @@
+// GDB remote serial protocol stub:
+// lets GDB (or any RSP client) attach over a local TCP port and debug the program
+// with breakpoints, watchpoints, single-stepping and register/memory access.
+// The stub is polled once per frame by the frontend and never blocks it:
+// packets are handled as they arrive and execution goes through the `Debugger`.
+//
+// Registers, in `g`/`G` order, big-endian like CHIP-8 itself:
+//   V0-VF (8 bits each), I (16), PC (16), SP (8, read-only), DT (8), ST (8)
+// described to the client by the target.xml served through qXfer.
+
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+use crate::debugger::{Debugger, Stop};
+use crate::error::Result;
+use crate::machine::Machine;
+use crate::ram::{Access, Watchpoint};
+
+const REGISTER_SIZES: [usize; 21] = [
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 1, 1, 1,
+];
+const PC_REGISTER: usize = 17;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8rs.cpu">
+    <reg name="v0" bitsize="8" regnum="0"/>
+    <reg name="v1" bitsize="8"/>
+    <reg name="v2" bitsize="8"/>
+    <reg name="v3" bitsize="8"/>
+    <reg name="v4" bitsize="8"/>
+    <reg name="v5" bitsize="8"/>
+    <reg name="v6" bitsize="8"/>
+    <reg name="v7" bitsize="8"/>
+    <reg name="v8" bitsize="8"/>
+    <reg name="v9" bitsize="8"/>
+    <reg name="va" bitsize="8"/>
+    <reg name="vb" bitsize="8"/>
+    <reg name="vc" bitsize="8"/>
+    <reg name="vd" bitsize="8"/>
+    <reg name="ve" bitsize="8"/>
+    <reg name="vf" bitsize="8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8"/>
+    <reg name="dt" bitsize="8"/>
+    <reg name="st" bitsize="8"/>
+  </feature>
+</target>
+"#;
+
+// Signals reported in stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+pub struct GdbStub {
+    listener: TcpListener,
+    stream: Option<TcpStream>,
+    input: Vec<u8>,
+    debugger: Debugger,
+    // Acknowledgements are dropped once the client asked for QStartNoAckMode
+    no_ack: bool,
+    // The client resumed execution and waits for a stop reply
+    running: bool,
+    // Signal of the last stop, reported again on '?'
+    signal: u8,
+}
+
+impl GdbStub {
+    // Listen on the loopback interface only, the protocol has no authentication
+    pub fn bind(port: u16) -> Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            stream: None,
+            input: Vec::new(),
+            debugger: Debugger::new(),
+            no_ack: false,
+            running: false,
+            signal: SIGTRAP,
+        })
+    }
+
+    pub fn port(&self) -> Result<u16> {
+        Ok(self.listener.local_addr()?.port())
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    // Handle the pending packets, then run a frame unless the program is halted
+    // The program stays halted until a client connects and resumes it
+    // Returns false once the client killed the program
+    pub fn run_frame(&mut self, machine: &mut Machine) -> Result<bool> {
+        if self.stream.is_none() {
+            self.accept()?;
+        }
+        if self.stream.is_some() {
+            match self.poll(machine) {
+                Ok(alive) if !alive => return Ok(false),
+                Ok(_) => {}
+                Err(e) if is_disconnection(&e) => self.disconnect(),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let result = self.debugger.run_frame(machine);
+        if self.running && (self.debugger.is_paused() || machine.has_exited()) {
+            self.running = false;
+            let reply = match &result {
+                _ if machine.has_exited() => "W00".to_string(),
+                Err(_) => self.stop_reply(SIGILL, None),
+                Ok(()) => {
+                    let stop = self.debugger.take_stop();
+                    self.stop_reply(SIGTRAP, stop)
+                }
+            };
+            if let Err(e) = self.send(&reply) {
+                if !is_disconnection(&e) {
+                    return Err(e.into());
+                }
+                self.disconnect();
+            }
+        }
+        // Execution errors are reported to the client instead of ending the session
+        Ok(true)
+    }
+
+    fn accept(&mut self) -> io::Result<()> {
+        match self.listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nodelay(true)?;
+                self.stream = Some(stream);
+                self.input.clear();
+                self.no_ack = false;
+                self.running = false;
+                // GDB expects the target to be halted when it attaches
+                self.debugger.pause();
+                Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Leave the program running when the client goes away
+    fn disconnect(&mut self) {
+        self.stream = None;
+        self.running = false;
+        self.debugger.resume();
+    }
+
+    fn poll(&mut self, machine: &mut Machine) -> io::Result<bool> {
+        self.read_available()?;
+        while let Some(packet) = self.next_packet()? {
+            if !self.handle(&packet, machine)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn read_available(&mut self) -> io::Result<()> {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => return Ok(()),
+        };
+        stream.set_nonblocking(true)?;
+        let mut buffer = [0; 4096];
+        let result = loop {
+            match stream.read(&mut buffer) {
+                Ok(0) => break Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(len) => self.input.extend_from_slice(&buffer[..len]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        stream.set_nonblocking(false)?;
+        result
+    }
+
+    // Extract the next complete packet, handling acks and interrupts (Ctrl+C) on the way
+    fn next_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.input.first() {
+                None => return Ok(None),
+                Some(b'$') => break,
+                Some(0x03) => {
+                    self.input.remove(0);
+                    self.debugger.pause();
+                    if self.running {
+                        self.running = false;
+                        let reply = self.stop_reply(SIGINT, None);
+                        self.send(&reply)?;
+                    }
+                }
+                // Acks ('+', '-') and noise between packets
+                Some(_) => {
+                    self.input.remove(0);
+                }
+            }
+        }
+
+        let end = match self.input.iter().position(|byte| *byte == b'#') {
+            Some(end) if end + 2 < self.input.len() => end,
+            _ => return Ok(None),
+        };
+        let packet: Vec<u8> = self.input.drain(..end + 3).collect();
+        let data = &packet[1..end];
+        let checksum = std::str::from_utf8(&packet[end + 1..])
+            .ok()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        if !self.no_ack {
+            let valid = checksum == Some(checksum_of(data));
+            self.write(if valid { b"+" } else { b"-" })?;
+            if !valid {
+                return self.next_packet();
+            }
+        }
+        Ok(Some(String::from_utf8_lossy(&unescape(data)).into_owned()))
+    }
+
+    fn handle(&mut self, packet: &str, machine: &mut Machine) -> io::Result<bool> {
+        // Packets are read lossily, the first character may take several bytes
+        let kind_len = packet.chars().next().map_or(0, char::len_utf8);
+        let (kind, args) = packet.split_at(kind_len);
+        let reply = match kind {
+            "?" => self.stop_reply(self.signal, None),
+            "g" => self.read_registers(machine),
+            "G" => self.write_registers(args, machine),
+            "p" => self.read_register(args, machine),
+            "P" => self.write_register(args, machine),
+            "m" => self.read_memory(args, machine),
+            "M" => self.write_memory(args, machine),
+            "c" | "s" => {
+                // An optional address to resume at
+                if let Some(address) = parse_hex(args) {
+                    machine.cpu_mut().pc = address;
+                }
+                if kind == "c" {
+                    self.debugger.resume();
+                } else {
+                    self.debugger.step();
+                }
+                self.running = true;
+                return Ok(true);
+            }
+            "Z" | "z" => self.breakpoint(kind == "Z", args, machine),
+            "H" | "T" => "OK".to_string(),
+            "D" => {
+                self.send("OK")?;
+                self.disconnect();
+                return Ok(true);
+            }
+            "k" => return Ok(false),
+            "q" | "Q" => self.query(packet),
+            _ => String::new(),
+        };
+        self.send(&reply)?;
+        Ok(true)
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+;swbreak+".to_string()
+        } else if packet == "QStartNoAckMode" {
+            self.no_ack = true;
+            "OK".to_string()
+        } else if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, end) = match parse_range(range).and_then(range_end) {
+                Some(range) => range,
+                None => return "E01".to_string(),
+            };
+            let xml = TARGET_XML.as_bytes();
+            let chunk = &xml[offset.min(xml.len())..end.min(xml.len())];
+            let more = end < xml.len();
+            format!(
+                "{}{}",
+                if more { 'm' } else { 'l' },
+                String::from_utf8_lossy(chunk)
+            )
+        } else {
+            match packet {
+                "qAttached" => "1".to_string(),
+                "qC" => "QC1".to_string(),
+                "qfThreadInfo" => "m1".to_string(),
+                "qsThreadInfo" => "l".to_string(),
+                _ => String::new(),
+            }
+        }
+    }
+
+    fn stop_reply(&mut self, signal: u8, stop: Option<Stop>) -> String {
+        self.signal = signal;
+        match stop {
+            Some(Stop::Watchpoint { hit, .. }) => {
+                let kind = match hit.access {
+                    Access::Write => "watch",
+                    Access::Read => "rwatch",
+                    Access::ReadWrite => "awatch",
+                };
+                format!("T{:02x}{}:{:x};", signal, kind, hit.address)
+            }
+            Some(Stop::Breakpoint(_)) => format!("T{:02x}swbreak:;", signal),
+            _ => format!("S{:02x}", signal),
+        }
+    }
+
+    fn read_registers(&self, machine: &Machine) -> String {
+        (0..REGISTER_SIZES.len())
+            .map(|idx| register_hex(machine, idx))
+            .collect()
+    }
+
+    fn write_registers(&self, args: &str, machine: &mut Machine) -> String {
+        let mut offset = 0;
+        for (idx, size) in REGISTER_SIZES.iter().enumerate() {
+            let hex = match args.get(offset..offset + size * 2) {
+                Some(hex) => hex,
+                None => return "E01".to_string(),
+            };
+            if let Some(value) = parse_hex(hex) {
+                set_register(machine, idx, value);
+            }
+            offset += size * 2;
+        }
+        "OK".to_string()
+    }
+
+    fn read_register(&self, args: &str, machine: &Machine) -> String {
+        match parse_hex(args) {
+            Some(idx) if idx < REGISTER_SIZES.len() => register_hex(machine, idx),
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn write_register(&self, args: &str, machine: &mut Machine) -> String {
+        let mut parts = args.splitn(2, '=');
+        let idx = parts.next().and_then(parse_hex);
+        let value = parts.next().and_then(parse_hex);
+        match (idx, value) {
+            (Some(idx), Some(value)) if idx < REGISTER_SIZES.len() => {
+                set_register(machine, idx, value);
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    // Memory reads and writes by the client don't trigger watchpoints
+    fn read_memory(&self, args: &str, machine: &Machine) -> String {
+        let (address, end) = match parse_range(args).and_then(range_end) {
+            Some(range) => range,
+            None => return "E01".to_string(),
+        };
+        match machine.cpu().ram.memory.get(address..end) {
+            Some(bytes) => bytes.iter().map(|byte| format!("{:02x}", byte)).collect(),
+            None => "E01".to_string(),
+        }
+    }
+
+    fn write_memory(&self, args: &str, machine: &mut Machine) -> String {
+        let mut parts = args.splitn(2, ':');
+        let range = parts.next().and_then(parse_range);
+        let data = parts.next().and_then(decode_hex);
+        match (range, data) {
+            (Some((address, length)), Some(data)) if data.len() == length => {
+                let end = match address.checked_add(length) {
+                    Some(end) => end,
+                    None => return "E01".to_string(),
+                };
+                match machine.cpu_mut().ram.memory.get_mut(address..end) {
+                    Some(bytes) => {
+                        bytes.copy_from_slice(&data);
+                        "OK".to_string()
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    // Z0/Z1: software/hardware breakpoint, Z2/Z3/Z4: write/read/access watchpoint
+    fn breakpoint(&mut self, insert: bool, args: &str, machine: &mut Machine) -> String {
+        let mut parts = args.split(',');
+        let kind = parts.next();
+        let address = parts.next().and_then(parse_hex);
+        let length = parts.next().and_then(parse_hex).unwrap_or(1).max(1);
+        let address = match address {
+            Some(address) => address,
+            None => return "E01".to_string(),
+        };
+        let access = match kind {
+            Some("0") | Some("1") => {
+                if insert {
+                    self.debugger.add_breakpoint(address);
+                } else {
+                    self.debugger.remove_breakpoint(address);
+                }
+                return "OK".to_string();
+            }
+            Some("2") => Access::Write,
+            Some("3") => Access::Read,
+            Some("4") => Access::ReadWrite,
+            _ => return String::new(),
+        };
+        if insert {
+            let end = match address.checked_add(length - 1) {
+                Some(end) => end,
+                None => return "E01".to_string(),
+            };
+            machine.add_watchpoint(Watchpoint {
+                start: address,
+                end,
+                access,
+            });
+        } else {
+            machine.remove_watchpoint(address);
+        }
+        "OK".to_string()
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.write(packet.as_bytes())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match &mut self.stream {
+            Some(stream) => stream.write_all(bytes),
+            None => Ok(()),
+        }
+    }
+}
+
+fn register_hex(machine: &Machine, idx: usize) -> String {
+    let cpu = machine.cpu();
+    let value = match idx {
+        0..=15 => cpu.v[idx] as usize,
+        16 => cpu.i,
+        PC_REGISTER => cpu.pc,
+        18 => cpu.stack().len(),
+        19 => cpu.delay_timer() as usize,
+        _ => cpu.sound_timer() as usize,
+    };
+    match REGISTER_SIZES[idx] {
+        2 => format!("{:04x}", value & 0xFFFF),
+        _ => format!("{:02x}", value & 0xFF),
+    }
+}
+
+fn set_register(machine: &mut Machine, idx: usize, value: usize) {
+    let cpu = machine.cpu_mut();
+    match idx {
+        0..=15 => cpu.v[idx] = value as u8,
+        16 => cpu.i = value,
+        PC_REGISTER => cpu.pc = value,
+        // The stack pointer follows the stack, which can't be edited
+        18 => {}
+        19 => cpu.set_delay_timer(value as u8),
+        _ => cpu.set_sound_timer(value as u8),
+    }
+}
+
+fn is_disconnection(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+    )
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+// '}' escapes the next byte, XORed with 0x20
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'}' => out.extend(bytes.next().map(|byte| byte ^ 0x20)),
+            _ => out.push(*byte),
+        }
+    }
+    out
+}
+
+fn parse_hex(hex: &str) -> Option<usize> {
+    usize::from_str_radix(hex, 16).ok()
+}
+
+// "addr,length"
+fn parse_range(args: &str) -> Option<(usize, usize)> {
+    let mut parts = args.splitn(2, ',');
+    let address = parse_hex(parts.next()?)?;
+    let length = parse_hex(parts.next()?)?;
+    Some((address, length))
+}
+
+// "addr,length" as (start, end), None if the end overflows
+fn range_end((address, length): (usize, usize)) -> Option<(usize, usize)> {
+    Some((address, address.checked_add(length)?))
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(hex.get(idx..idx + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    // A client on the loopback interface, polling the stub between packets
+    struct Client {
+        stream: TcpStream,
+        stub: GdbStub,
+        machine: Machine,
+    }
+
+    impl Client {
+        fn connect(rom: &[u8]) -> Self {
+            let mut stub = GdbStub::bind(0).unwrap();
+            let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, stub.port().unwrap())).unwrap();
+            stream.set_nonblocking(true).unwrap();
+            let mut machine = Machine::new(rom).unwrap();
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while !stub.is_connected() {
+                assert!(
+                    Instant::now() < deadline,
+                    "the stub didn't accept the client"
+                );
+                stub.run_frame(&mut machine).unwrap();
+            }
+            Self {
+                stream,
+                stub,
+                machine,
+            }
+        }
+
+        // Send a packet and run frames until the reply arrives, acks are skipped
+        fn request(&mut self, data: &str) -> String {
+            self.request_bytes(data.as_bytes())
+        }
+
+        fn request_bytes(&mut self, data: &[u8]) -> String {
+            let mut packet = vec![b'$'];
+            packet.extend_from_slice(data);
+            packet.extend_from_slice(format!("#{:02x}", checksum_of(data)).as_bytes());
+            self.stream.write_all(&packet).unwrap();
+            let deadline = Instant::now() + Duration::from_secs(5);
+            let mut input = Vec::new();
+            loop {
+                assert!(
+                    Instant::now() < deadline,
+                    "no reply to `{}`",
+                    String::from_utf8_lossy(data)
+                );
+                self.stub.run_frame(&mut self.machine).unwrap();
+                let mut buffer = [0; 4096];
+                match self.stream.read(&mut buffer) {
+                    Ok(len) => input.extend_from_slice(&buffer[..len]),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(e) => panic!("{}", e),
+                }
+                let text = String::from_utf8_lossy(&input).into_owned();
+                let text = text.trim_start_matches('+');
+                if let (Some(start), Some(end)) = (text.find('$'), text.find('#')) {
+                    if end + 2 < text.len() {
+                        return text[start + 1..end].to_string();
+                    }
+                }
+            }
+        }
+    }
+
+    // 0x200: LD V0, 5  0x202: ADD V0, 1  0x204: JP 0x202
+    const ROM: [u8; 6] = [0x60, 0x05, 0x70, 0x01, 0x12, 0x02];
+
+    #[test]
+    fn reads_and_writes_registers_and_memory() {
+        let mut client = Client::connect(&ROM);
+        let registers = client.request("g");
+        assert_eq!(registers.len(), 2 * REGISTER_SIZES.iter().sum::<usize>());
+        assert_eq!(&registers[..2], "00");
+        assert_eq!(&registers[36..40], "0200");
+
+        assert_eq!(client.request("m200,6"), "600570011202");
+        assert_eq!(client.request("M300,2:abcd"), "OK");
+        assert_eq!(client.request("m300,2"), "abcd");
+        assert_eq!(client.machine.cpu().ram.memory[0x300], 0xAB);
+    }
+
+    #[test]
+    fn stops_at_breakpoints() {
+        let mut client = Client::connect(&ROM);
+        assert_eq!(client.request("Z0,204,2"), "OK");
+        assert_eq!(client.request("c"), "T05swbreak:;");
+        let registers = client.request("g");
+        assert_eq!(&registers[..2], "06");
+        assert_eq!(&registers[36..40], "0204");
+    }
+
+    #[test]
+    fn rejects_ranges_that_overflow() {
+        let mut client = Client::connect(&ROM);
+        assert_eq!(client.request("mffffffffffffffff,10"), "E01");
+        assert_eq!(client.request("Mffffffffffffffff,1:00"), "E01");
+        assert_eq!(client.request("Z2,ffffffffffffffff,2"), "E01");
+        assert_eq!(
+            client.request("qXfer:features:read:target.xml:ffffffffffffffff,10"),
+            "E01"
+        );
+        assert_eq!(client.request("m1000,1"), "E01");
+    }
+
+    #[test]
+    fn ignores_packets_starting_with_other_characters() {
+        let mut client = Client::connect(&ROM);
+        assert_eq!(client.request_bytes(b"\x80m200,2"), "");
+        assert_eq!(client.request_bytes("émoji".as_bytes()), "");
+        assert_eq!(client.request("m200,2"), "6005");
+    }
+}
@@ -0,0 +1,82 @@
+// RGB images of the display, saved and loaded as PNG files:
+// one image pixel per display pixel times the scale, in the colours of the window.
+
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use chiprs::Machine;
+
+use crate::chip8::PALETTE;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    // 0xRRGGBB, row by row
+    pub pixels: Vec<u32>,
+}
+
+impl Image {
+    pub fn from_machine(machine: &Machine, scale: usize) -> Self {
+        let (width, height) = machine.display_size();
+        let framebuffer = machine.framebuffer();
+        let mut pixels = Vec::with_capacity(width * height * scale * scale);
+        for y in 0..height * scale {
+            for x in 0..width * scale {
+                let pixel = framebuffer[y / scale * width + x / scale];
+                pixels.push(PALETTE[pixel as usize & 0x3]);
+            }
+        }
+        Self {
+            width: width * scale,
+            height: height * scale,
+            pixels,
+        }
+    }
+
+    pub fn save_png(&self, path: &Path) -> io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let data: Vec<u8> = self
+            .pixels
+            .iter()
+            .flat_map(|rgb| {
+                let [_, r, g, b] = rgb.to_be_bytes();
+                [r, g, b]
+            })
+            .collect();
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&data))
+            .map_err(io::Error::other)
+    }
+
+    pub fn load_png(path: &Path) -> io::Result<Self> {
+        let invalid = |e: png::DecodingError| io::Error::new(io::ErrorKind::InvalidData, e);
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        // Palette and low bit depth images are expanded to 8 bits per channel
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info().map_err(invalid)?;
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).map_err(invalid)?;
+        let channels = info.color_type.samples();
+        let pixels = data[..info.buffer_size()]
+            .chunks(channels)
+            .map(|channel| match info.color_type {
+                png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha => {
+                    let gray = channel[0] as u32;
+                    gray << 16 | gray << 8 | gray
+                }
+                _ => (channel[0] as u32) << 16 | (channel[1] as u32) << 8 | channel[2] as u32,
+            })
+            .collect();
+        Ok(Self {
+            width: info.width as usize,
+            height: info.height as usize,
+            pixels,
+        })
+    }
+}
@@ -0,0 +1,23 @@
+pub mod assembler;
+pub mod bus;
+pub mod condition;
+pub mod cpu;
+pub mod debugger;
+pub mod disassembler;
+pub mod display;
+pub mod error;
+pub mod font;
+pub mod gdb;
+pub mod machine;
+pub mod movie;
+pub mod quirks;
+pub mod ram;
+pub mod rewind;
+pub mod rng;
+pub mod scheduler;
+pub mod snapshot;
+pub mod trace;
+
+pub use crate::error::{Error, Result};
+pub use crate::machine::Machine;
+pub use crate::quirks::Quirks;
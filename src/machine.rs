@@ -0,0 +1,250 @@
+// Headless CHIP-8 machine:
+// owns the CPU (with its RAM and timers) and the bus (display and keypad),
+// without any knowledge of windows, audio devices or keyboards.
+// Frontends feed it keys, run it frame by frame and read back
+// the frame buffer and the state of the sound timer.
+// A frame is one 60 Hz tick of the timers, see `scheduler` for pacing frames in real time.
+
+use crate::bus::Bus;
+use crate::cpu::Cpu;
+use crate::error::Result;
+use crate::quirks::Quirks;
+use crate::ram::Watchpoint;
+use crate::snapshot::Snapshot;
+use crate::trace::Tracer;
+
+// Default number of instructions executed per 60 Hz frame, between two timer ticks
+pub const DEFAULT_CYCLES_PER_FRAME: usize = 10;
+
+pub struct Machine {
+    cpu: Cpu,
+    bus: Bus,
+    cycles_per_frame: usize,
+    // Instructions already executed in the current frame, when a frame was interrupted
+    frame_cycles: usize,
+    // Frames completed since power on, not part of save states
+    frames: u64,
+    redraw: bool,
+    // A state was loaded, the frame after it is redrawn even if the program doesn't draw
+    restored: bool,
+}
+
+impl Machine {
+    pub fn new(rom_buffer: &[u8]) -> Result<Self> {
+        Self::with_quirks(rom_buffer, Quirks::default())
+    }
+
+    pub fn with_quirks(rom_buffer: &[u8], quirks: Quirks) -> Result<Self> {
+        Ok(Self {
+            cpu: Cpu::new(rom_buffer, quirks)?,
+            bus: Bus::new(),
+            cycles_per_frame: DEFAULT_CYCLES_PER_FRAME,
+            frame_cycles: 0,
+            frames: 0,
+            redraw: false,
+            restored: false,
+        })
+    }
+
+    // Execute a single instruction
+    // On error the faulty instruction is not executed and the PC still points at it
+    pub fn step(&mut self) -> Result<()> {
+        self.cpu.execute_cycle(&mut self.bus)?;
+        if self.cpu.should_redraw() {
+            self.redraw = true;
+        }
+        Ok(())
+    }
+
+    // Execute one frame worth of instructions, then tick the timers once
+    // With the display wait quirk, the frame ends early as soon as a sprite is drawn
+    pub fn run_frame(&mut self) -> Result<()> {
+        self.run_until(|_| false)?;
+        Ok(())
+    }
+
+    // Execute the rest of the current frame, asking `stop` before each instruction
+    // Returns false if `stop` interrupted the frame, the next call then resumes it where it stopped
+    // (used by the debugger to halt on breakpoints without losing track of the timers)
+    pub fn run_until<F: FnMut(&Cpu) -> bool>(&mut self, mut stop: F) -> Result<bool> {
+        while self.frame_cycles < self.cycles_per_frame {
+            if stop(&self.cpu) {
+                return Ok(false);
+            }
+            if self.frame_cycles == 0 {
+                self.redraw = std::mem::take(&mut self.restored);
+            }
+            self.step()?;
+            self.frame_cycles += 1;
+            if self.cpu.should_redraw() && self.cpu.quirks.display_wait {
+                break;
+            }
+        }
+        self.frame_cycles = 0;
+        self.frames += 1;
+        self.cpu.update_timers();
+        Ok(true)
+    }
+
+    // One byte per pixel, holding one bit per bitplane (XO-CHIP), so values range from 0 to 3
+    pub fn framebuffer(&self) -> &[u8] {
+        self.bus.display.get_frame_buffer()
+    }
+
+    // Held keys as a bitmask: bit N is set while CHIP-8 key N (0x0 - 0xF) is down
+    pub fn set_keys(&mut self, keys: u16) {
+        self.bus.set_keys(keys);
+    }
+
+    pub fn keys(&self) -> u16 {
+        self.bus.keys
+    }
+
+    // Current resolution of the display (width, height)
+    pub fn display_size(&self) -> (usize, usize) {
+        (self.bus.display.width(), self.bus.display.height())
+    }
+
+    // True once the program executed 00FD (SUPER-CHIP exit)
+    pub fn has_exited(&self) -> bool {
+        self.cpu.has_exited()
+    }
+
+    // True while the CPU is halted on FX0A waiting for a key press and release
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.cpu.is_waiting_for_key()
+    }
+
+    // SUPER-CHIP RPL user flags, which frontends may persist between runs
+    pub fn rpl_flags(&self) -> [u8; 16] {
+        *self.cpu.rpl_flags()
+    }
+
+    pub fn set_rpl_flags(&mut self, flags: [u8; 16]) {
+        self.cpu.set_rpl_flags(flags);
+    }
+
+    pub fn audio_active(&self) -> bool {
+        self.cpu.should_beep()
+    }
+
+    // XO-CHIP audio pattern buffer (128 1-bit samples) and its playback rate in Hz
+    pub fn audio_pattern(&self) -> &[u8; 16] {
+        self.cpu.audio_pattern()
+    }
+
+    pub fn audio_playback_rate(&self) -> f32 {
+        self.cpu.audio_playback_rate()
+    }
+
+    // True if the display changed since the start of the last frame, or a state was loaded before it
+    pub fn should_redraw(&self) -> bool {
+        self.redraw
+    }
+
+    // Serialize the full machine state, see `snapshot` for the format
+    pub fn save_state(&self) -> Vec<u8> {
+        self.snapshot().to_bytes()
+    }
+
+    // Restore a state produced by `save_state`, the machine is left untouched on error
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<()> {
+        let snapshot = Snapshot::from_bytes(bytes)?;
+        self.restore(&snapshot)
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let mut snapshot = Snapshot::default();
+        self.cpu.save_state(&mut snapshot);
+        self.bus.save_state(&mut snapshot);
+        snapshot
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<()> {
+        snapshot.validate()?;
+        self.cpu.load_state(snapshot);
+        self.bus.load_state(snapshot);
+        self.frame_cycles = 0;
+        self.redraw = true;
+        self.restored = true;
+        Ok(())
+    }
+
+    // Number of frames completed so far, e.g. to index the frames of a movie
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn cycles_per_frame(&self) -> usize {
+        self.cycles_per_frame
+    }
+
+    pub fn set_cycles_per_frame(&mut self, cycles: usize) {
+        self.cycles_per_frame = cycles.max(1);
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.cpu.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.cpu.quirks = quirks;
+    }
+
+    // Debugger watchpoints on RAM, see `Debugger` for stopping on them
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.cpu.ram.add_watchpoint(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, start: usize) -> bool {
+        self.cpu.ram.remove_watchpoint(start)
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        self.cpu.ram.watchpoints()
+    }
+
+    // Seed the random numbers of CXKK, two machines with the same seed and inputs behave identically
+    pub fn set_seed(&mut self, seed: u64) {
+        self.cpu.set_seed(seed);
+    }
+
+    // Instruction trace, None stops tracing
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.cpu.set_tracer(tracer);
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    // Direct access to registers and memory, for debuggers
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    pub fn bus(&self) -> &Bus {
+        &self.bus
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redraws_after_loading_a_state() {
+        // JP 0x200, never draws
+        let mut machine = Machine::new(&[0x12, 0x00]).unwrap();
+        machine.run_frame().unwrap();
+        let state = machine.save_state();
+        machine.run_frame().unwrap();
+        assert!(!machine.should_redraw());
+
+        machine.load_state(&state).unwrap();
+        machine.run_frame().unwrap();
+        assert!(machine.should_redraw());
+        machine.run_frame().unwrap();
+        assert!(!machine.should_redraw());
+    }
+}
@@ -1,35 +1,121 @@
-#[allow(dead_code)]
-mod assembler;
-mod bus;
+mod audio;
 mod chip8;
-mod cpu;
-mod disassembler;
-mod display;
-mod font;
-mod ram;
-use crate::{assembler::Assembler, chip8::Chip8};
-use crate::disassembler::Disassembler;
+mod console;
+mod image;
+mod screenshot;
+mod terminal;
+mod test_runner;
+mod tui;
+use crate::chip8::{Chip8, Options};
+use crate::terminal::Charset;
+use chiprs::assembler::Assembler;
+use chiprs::disassembler::Disassembler;
+use chiprs::trace::{TraceFilter, TraceFormat};
+use chiprs::Quirks;
+use std::path::Path;
 
 #[macro_use]
 extern crate clap;
-use clap::App;
+use clap::{App, ArgMatches};
 
 fn main() {
     let yaml = load_yaml!("cli.yml");
     let matches = App::from_yaml(yaml).get_matches();
+    if let Some(matches) = matches.subcommand_matches("test") {
+        let manifest = matches.value_of("MANIFEST").expect("Unable to read file.");
+        let output = matches.value_of("output").map(Path::new);
+        match test_runner::run(Path::new(manifest), output) {
+            Ok(true) => return,
+            Ok(false) => std::process::exit(1),
+            Err(e) => exit_with_error(e),
+        }
+    }
     let source_file = matches
         .value_of("INPUT")
         .expect("Unable to read file.")
         .to_string();
 
     if matches.is_present("disassemble") {
-        let disassembler = Disassembler::new(source_file);
+        let disassembler = Disassembler::new(source_file).unwrap_or_else(|e| exit_with_error(e));
         disassembler.run();
     } else if matches.is_present("assemble") {
         let assembler = Assembler::new(source_file);
-        assembler.run();
+        if !assembler.run() {
+            std::process::exit(1);
+        }
     } else {
-        let mut chip8 = Chip8::new(source_file);
-        chip8.run();
+        let options = Options {
+            quirks: Quirks {
+                vip_rng: matches.is_present("vip-rng"),
+                ..matches
+                    .value_of("quirks")
+                    .and_then(Quirks::from_name)
+                    .unwrap_or_default()
+            },
+            seed: matches
+                .value_of("seed")
+                .map(|_| value_t!(matches, "seed", u64).unwrap_or_else(|e| e.exit())),
+            cycles_per_frame: value_t!(matches, "ipf", usize).unwrap_or_else(|e| e.exit()),
+            rewind_budget: value_t!(matches, "rewind", usize).unwrap_or_else(|e| e.exit())
+                * 1024
+                * 1024,
+            debug: matches.is_present("debug"),
+            tui: matches.is_present("tui"),
+            gdb_port: matches
+                .value_of("gdb")
+                .map(|_| value_t!(matches, "gdb", u16).unwrap_or_else(|e| e.exit())),
+            trace_file: matches.value_of("trace").map(String::from),
+            trace_format: match matches.value_of("trace-format") {
+                Some("binary") => TraceFormat::Binary,
+                _ => TraceFormat::Text,
+            },
+            trace_filter: trace_filter(&matches),
+            record: matches.value_of("record").map(String::from),
+            replay: matches.value_of("replay").map(String::from),
+            screenshot_scale: value_t!(matches, "scale", usize).unwrap_or_else(|e| e.exit()),
+        };
+        let mut chip8 = Chip8::new(source_file, options).unwrap_or_else(|e| exit_with_error(e));
+        let result = if let Some(path) = matches.value_of("screenshot") {
+            let frames = value_t!(matches, "frames", u64).unwrap_or_else(|e| e.exit());
+            chip8.run_headless(frames, Path::new(path))
+        } else if matches.is_present("terminal") {
+            let charset = if matches.is_present("braille") {
+                Charset::Braille
+            } else {
+                Charset::HalfBlock
+            };
+            chip8.run_in_terminal(charset)
+        } else {
+            chip8.run()
+        };
+        // Flush the trace before exiting, it matters most when execution failed
+        drop(chip8);
+        if let Err(e) = result {
+            exit_with_error(e);
+        }
     };
 }
+
+fn trace_filter(matches: &ArgMatches) -> TraceFilter {
+    let mut filter = TraceFilter::default();
+    for range in matches.values_of("trace-range").into_iter().flatten() {
+        filter
+            .add_range(range)
+            .unwrap_or_else(|e| exit_with_usage(&e));
+    }
+    for class in matches.values_of("trace-ops").into_iter().flatten() {
+        filter
+            .add_class(class)
+            .unwrap_or_else(|e| exit_with_usage(&e));
+    }
+    filter
+}
+
+fn exit_with_usage(message: &str) -> ! {
+    clap::Error::with_description(message, clap::ErrorKind::InvalidValue).exit()
+}
+
+fn exit_with_error(error: chiprs::Error) -> ! {
+    eprintln!("Error: {}", error);
+    std::process::exit(1);
+}
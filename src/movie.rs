@@ -0,0 +1,179 @@
+// Movies:
+// the keypad state of every frame, recorded with everything else needed
+// to play a run back exactly: the ROM, the quirks, the speed and the RNG seed.
+// Used for bug reports, speedrun verification and regression tests.
+//
+// File format (all integers little-endian):
+//   magic "C8MV", format version (u16)
+//   SHA-1 of the ROM (20 bytes)
+//   quirks (u8 bit flags), instructions per frame (u32), RNG seed (u64)
+//   frame count (u32)
+//   keypad runs covering the frame count: held keys (u16 bitmask), length in frames (u16)
+
+use crate::error::{Error, Result};
+use crate::machine::Machine;
+use crate::quirks::Quirks;
+
+const MAGIC: &[u8; 4] = b"C8MV";
+pub const VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    pub rom_hash: [u8; 20],
+    pub quirks: Quirks,
+    pub cycles_per_frame: usize,
+    pub seed: u64,
+    // Held keys of each frame
+    frames: Vec<u16>,
+}
+
+impl Movie {
+    // Start recording a run of `rom`, the machine must be set up with the same settings
+    pub fn new(rom: &[u8], quirks: Quirks, cycles_per_frame: usize, seed: u64) -> Self {
+        Self {
+            rom_hash: rom_hash(rom),
+            quirks,
+            cycles_per_frame,
+            seed,
+            frames: Vec::new(),
+        }
+    }
+
+    // A machine in the state the recording started from
+    pub fn machine(&self, rom: &[u8]) -> Result<Machine> {
+        if rom_hash(rom) != self.rom_hash {
+            return Err(Error::InvalidMovie(format!(
+                "recorded with another ROM (SHA-1 {})",
+                hex::encode(self.rom_hash)
+            )));
+        }
+        let mut machine = Machine::with_quirks(rom, self.quirks)?;
+        machine.set_cycles_per_frame(self.cycles_per_frame);
+        machine.set_seed(self.seed);
+        Ok(machine)
+    }
+
+    pub fn push(&mut self, keys: u16) {
+        self.frames.push(keys);
+    }
+
+    // Keys held during a frame, None past the end of the recording
+    pub fn keys(&self, frame: u64) -> Option<u16> {
+        self.frames.get(frame as usize).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&self.rom_hash);
+        out.push(self.quirks.to_bits());
+        out.extend_from_slice(&(self.cycles_per_frame as u32).to_le_bytes());
+        out.extend_from_slice(&self.seed.to_le_bytes());
+        out.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+
+        let mut frames = self.frames.iter().peekable();
+        while let Some(keys) = frames.next() {
+            let mut length: u16 = 1;
+            while length < u16::MAX && frames.peek() == Some(&keys) {
+                frames.next();
+                length += 1;
+            }
+            out.extend_from_slice(&keys.to_le_bytes());
+            out.extend_from_slice(&length.to_le_bytes());
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Movie> {
+        let mut position = 0;
+        let mut take = |len: usize| {
+            let field = bytes
+                .get(position..position + len)
+                .ok_or_else(|| Error::InvalidMovie(String::from("unexpected end of file")))?;
+            position += len;
+            Ok::<_, Error>(field)
+        };
+        let u16_at = |field: &[u8]| u16::from_le_bytes([field[0], field[1]]);
+
+        if take(4)? != MAGIC {
+            return Err(Error::InvalidMovie(String::from("not a CHIP-8 movie")));
+        }
+        let version = u16_at(take(2)?);
+        if version != VERSION {
+            return Err(Error::InvalidMovie(format!(
+                "unsupported movie version {} (expected {})",
+                version, VERSION
+            )));
+        }
+        let mut rom_hash = [0; 20];
+        rom_hash.copy_from_slice(take(20)?);
+        let quirks = Quirks::from_bits(take(1)?[0]);
+        let mut word = [0; 4];
+        word.copy_from_slice(take(4)?);
+        let cycles_per_frame = u32::from_le_bytes(word) as usize;
+        let mut seed = [0; 8];
+        seed.copy_from_slice(take(8)?);
+        word.copy_from_slice(take(4)?);
+        let count = u32::from_le_bytes(word) as usize;
+
+        // Not reserved up front: a corrupt count would ask for gigabytes,
+        // the runs can only grow the frames as far as the file goes
+        let mut frames = Vec::new();
+        while frames.len() < count {
+            let keys = u16_at(take(2)?);
+            let length = u16_at(take(2)?) as usize;
+            if length == 0 || frames.len() + length > count {
+                return Err(Error::InvalidMovie(String::from(
+                    "keypad runs don't match the frame count",
+                )));
+            }
+            frames.resize(frames.len() + length, keys);
+        }
+
+        Ok(Movie {
+            rom_hash,
+            quirks,
+            cycles_per_frame,
+            seed: u64::from_le_bytes(seed),
+            frames,
+        })
+    }
+}
+
+pub fn rom_hash(rom: &[u8]) -> [u8; 20] {
+    sha1_smol::Sha1::from(rom).digest().bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_bytes() {
+        let mut movie = Movie::new(&[0x12, 0x00], Quirks::COSMAC_VIP, 15, 42);
+        for keys in [0, 0, 0x10, 0x10, 0x10, 0] {
+            movie.push(keys);
+        }
+        assert_eq!(Movie::from_bytes(&movie.to_bytes()).unwrap(), movie);
+    }
+
+    #[test]
+    fn rejects_a_frame_count_without_runs() {
+        let mut bytes = Movie::new(&[0x12, 0x00], Quirks::COSMAC_VIP, 15, 42).to_bytes();
+        let count = bytes.len() - 4;
+        bytes[count..].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            Movie::from_bytes(&bytes),
+            Err(Error::InvalidMovie(_))
+        ));
+    }
+}
@@ -0,0 +1,140 @@
+// CHIP-8 quirks:
+// the various CHIP-8 interpreters (COSMAC VIP, CHIP-48, SUPER-CHIP, ...)
+// disagree on the behaviour of a handful of opcodes.
+// ROMs written for one interpreter often misbehave on another,
+// so each of these behaviours can be toggled individually
+// or selected as a whole through one of the named presets.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quirks {
+    // 8XY6/8XYE: shift Vy and store the result in Vx, instead of shifting Vx in place
+    pub shift_uses_vy: bool,
+    // FX55/FX65: increment I by X + 1 after storing/loading registers
+    pub load_store_increments_i: bool,
+    // BNNN: jump to XNN + VX instead of NNN + V0
+    pub jump_uses_vx: bool,
+    // 8XY1/8XY2/8XY3: reset VF to 0 after the logic operation
+    pub vf_reset: bool,
+    // DXYN: clip sprites at the edges of the screen instead of wrapping them around
+    pub clip_sprites: bool,
+    // DXYN: wait for the next frame (vertical blank) after drawing a sprite
+    pub display_wait: bool,
+    // XO-CHIP extensions: 64 KiB of memory, bitplanes, audio patterns and the new opcodes
+    pub xo_chip: bool,
+    // CXKK: generate random numbers like the COSMAC VIP interpreter instead of xorshift, see `rng`
+    pub vip_rng: bool,
+}
+
+impl Quirks {
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        jump_uses_vx: false,
+        vf_reset: true,
+        clip_sprites: true,
+        display_wait: true,
+        xo_chip: false,
+        vip_rng: false,
+    };
+
+    pub const CHIP_48: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: true,
+        jump_uses_vx: true,
+        vf_reset: false,
+        clip_sprites: true,
+        display_wait: false,
+        xo_chip: false,
+        vip_rng: false,
+    };
+
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: false,
+        jump_uses_vx: true,
+        vf_reset: false,
+        clip_sprites: true,
+        display_wait: false,
+        xo_chip: false,
+        vip_rng: false,
+    };
+
+    pub const MODERN: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        jump_uses_vx: false,
+        vf_reset: false,
+        clip_sprites: false,
+        display_wait: false,
+        xo_chip: false,
+        vip_rng: false,
+    };
+
+    // The behaviour of chip8rs before the presets existed, kept as the default:
+    // shifts of Vx in place, FX55/FX65 leaving I alone, BNNN adding V0, wrapping sprites
+    pub const LEGACY: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: false,
+        jump_uses_vx: false,
+        vf_reset: false,
+        clip_sprites: false,
+        display_wait: false,
+        xo_chip: false,
+        vip_rng: false,
+    };
+
+    pub const XO_CHIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        jump_uses_vx: false,
+        vf_reset: false,
+        clip_sprites: false,
+        display_wait: false,
+        xo_chip: true,
+        vip_rng: false,
+    };
+
+    // Look up a preset by the name used on the command line
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name.to_lowercase().as_str() {
+            "vip" | "cosmac" | "cosmac-vip" => Some(Quirks::COSMAC_VIP),
+            "chip48" | "chip-48" => Some(Quirks::CHIP_48),
+            "schip" | "superchip" | "super-chip" => Some(Quirks::SUPER_CHIP),
+            "modern" => Some(Quirks::MODERN),
+            "legacy" => Some(Quirks::LEGACY),
+            "xochip" | "xo-chip" => Some(Quirks::XO_CHIP),
+            _ => None,
+        }
+    }
+
+    // Pack the quirks into bit flags, used by save states and movie files
+    pub fn to_bits(&self) -> u8 {
+        self.shift_uses_vy as u8
+            | (self.load_store_increments_i as u8) << 1
+            | (self.jump_uses_vx as u8) << 2
+            | (self.vf_reset as u8) << 3
+            | (self.clip_sprites as u8) << 4
+            | (self.display_wait as u8) << 5
+            | (self.xo_chip as u8) << 6
+            | (self.vip_rng as u8) << 7
+    }
+
+    pub fn from_bits(flags: u8) -> Quirks {
+        Quirks {
+            shift_uses_vy: flags & 1 != 0,
+            load_store_increments_i: flags & 1 << 1 != 0,
+            jump_uses_vx: flags & 1 << 2 != 0,
+            vf_reset: flags & 1 << 3 != 0,
+            clip_sprites: flags & 1 << 4 != 0,
+            display_wait: flags & 1 << 5 != 0,
+            xo_chip: flags & 1 << 6 != 0,
+            vip_rng: flags & 1 << 7 != 0,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::LEGACY
+    }
+}
@@ -1,36 +1,165 @@
+use crate::error::{Error, Result};
 use crate::font;
+use std::cell::Cell;
 
-const RAM_SIZE: usize = 4096;
-const START_ROM: usize = 512; // 0x200
+pub const RAM_SIZE: usize = 4096;
+// XO-CHIP extends the address space to 64 KiB
+pub const XO_RAM_SIZE: usize = 65536;
+pub const START_ROM: usize = 512; // 0x200
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn includes(self, access: Access) -> bool {
+        self == Access::ReadWrite || self == access
+    }
+}
+
+// Debugger watchpoint on an inclusive range of addresses
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Watchpoint {
+    pub start: usize,
+    pub end: usize,
+    pub access: Access,
+}
+
+// First watched access made by an instruction, with the byte read or written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatchHit {
+    pub address: usize,
+    pub access: Access,
+    pub value: u8,
+}
 
 pub struct Ram {
-    pub memory: [u8; RAM_SIZE],
+    pub memory: Vec<u8>,
+    // Checked on every data access, which costs a length check while empty.
+    // Reads go through &self, so hits are recorded in a Cell
+    watchpoints: Vec<Watchpoint>,
+    watch_hit: Cell<Option<WatchHit>>,
 }
 
 impl Ram {
-    pub fn new(rom_buffer: &[u8]) -> Self {
-        let mut memory = [0; RAM_SIZE];
+    pub fn new(rom_buffer: &[u8]) -> Result<Self> {
+        Self::with_size(rom_buffer, RAM_SIZE)
+    }
+
+    pub fn with_size(rom_buffer: &[u8], size: usize) -> Result<Self> {
+        if rom_buffer.len() > size - START_ROM {
+            return Err(Error::RomTooLarge {
+                size: rom_buffer.len(),
+                max: size - START_ROM,
+            });
+        }
+
+        let mut memory = vec![0; size];
 
         // Load the font set into the first 80 bytes
         for (idx, byte) in font::FONT_SET.iter().flatten().enumerate() {
             memory[idx] = *byte;
         }
 
+        // Load the SUPER-CHIP big font set right after it
+        for (idx, byte) in font::BIG_FONT_SET.iter().flatten().enumerate() {
+            memory[font::BIG_FONT_START + idx] = *byte;
+        }
+
         // Load ROM into memory starting at 0x200
         memory[START_ROM..(rom_buffer.len() + START_ROM)].clone_from_slice(rom_buffer);
 
-        Self { memory }
+        Ok(Self {
+            memory,
+            watchpoints: Vec::new(),
+            watch_hit: Cell::new(None),
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.memory.len()
     }
 
-    pub fn write_byte(&mut self, index: usize, byte: u8) {
-        self.memory[index] = byte;
+    pub fn write_byte(&mut self, index: usize, byte: u8) -> Result<()> {
+        let cell = self
+            .memory
+            .get_mut(index)
+            .ok_or(Error::MemoryOutOfBounds { addr: index })?;
+        *cell = byte;
+        if !self.watchpoints.is_empty() {
+            self.watch(index, &[byte], Access::Write);
+        }
+        Ok(())
+    }
+
+    pub fn read_byte(&self, index: usize) -> Result<u8> {
+        let byte = self.peek_byte(index)?;
+        if !self.watchpoints.is_empty() {
+            self.watch(index, &[byte], Access::Read);
+        }
+        Ok(byte)
+    }
+
+    pub fn read_bytes(&self, start_idx: usize, stop_idx: usize) -> Result<&[u8]> {
+        let bytes = self
+            .memory
+            .get(start_idx..stop_idx)
+            .ok_or(Error::MemoryOutOfBounds {
+                addr: stop_idx.max(1) - 1,
+            })?;
+        if !self.watchpoints.is_empty() {
+            self.watch(start_idx, bytes, Access::Read);
+        }
+        Ok(bytes)
+    }
+
+    // Read which doesn't trigger watchpoints: instruction fetches and debugger views
+    pub fn peek_byte(&self, index: usize) -> Result<u8> {
+        self.memory
+            .get(index)
+            .copied()
+            .ok_or(Error::MemoryOutOfBounds { addr: index })
     }
 
-    pub fn read_byte(&self, index: usize) -> u8 {
-        self.memory[index]
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
     }
 
-    pub fn read_bytes(&self, start_idx: usize, stop_idx: usize) -> &[u8] {
-        &self.memory[start_idx..stop_idx]
+    // Remove the watchpoints starting at the address, returns true if there were any
+    pub fn remove_watchpoint(&mut self, start: usize) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints
+            .retain(|watchpoint| watchpoint.start != start);
+        self.watchpoints.len() != count
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    // The first watched access since the last call
+    pub fn take_watch_hit(&self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
+
+    fn watch(&self, start: usize, bytes: &[u8], access: Access) {
+        if self.watch_hit.get().is_some() || bytes.is_empty() {
+            return;
+        }
+        let end = start + bytes.len() - 1;
+        let watched = self.watchpoints.iter().find(|watchpoint| {
+            watchpoint.access.includes(access) && watchpoint.start <= end && start <= watchpoint.end
+        });
+        if let Some(watchpoint) = watched {
+            let address = start.max(watchpoint.start);
+            self.watch_hit.set(Some(WatchHit {
+                address,
+                access,
+                value: bytes[address - start],
+            }));
+        }
     }
 }
@@ -0,0 +1,199 @@
+// Rewind buffer:
+// keeps a history of save states, one per frame, within a memory budget.
+// Only the most recent state is stored in full. Each older state is stored
+// as the XOR difference with the state that followed it, run-length encoded:
+// consecutive frames differ in a handful of bytes, so a delta is tiny.
+// Rewinding applies the deltas backwards from the most recent state,
+// and the oldest deltas are dropped once the budget is exceeded.
+
+use std::collections::VecDeque;
+
+pub struct RewindBuffer {
+    budget: usize,
+    used: usize,
+    head: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl RewindBuffer {
+    // Budget in bytes for the whole history, including the full most recent state
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            used: 0,
+            head: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    // Record the state of a new frame
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(head) = self.head.take() {
+            let delta = encode_delta(&state, &head);
+            self.used = self.used - head.len() + delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.used += state.len();
+        self.head = Some(state);
+
+        while self.used > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.used -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    // Step back one frame, returning the state preceding the most recent one
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let delta = self.deltas.pop_back()?;
+        let head = self.head.as_mut()?;
+        let previous = decode_delta(head, &delta);
+        self.used = self.used - delta.len() - head.len() + previous.len();
+        *head = previous.clone();
+        Some(previous)
+    }
+
+    pub fn clear(&mut self) {
+        self.used = 0;
+        self.head = None;
+        self.deltas.clear();
+    }
+
+    // Number of frames that can be rewound
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    // Memory used by the history in bytes
+    pub fn memory_used(&self) -> usize {
+        self.used
+    }
+}
+
+// Delta turning `from` back into `to`:
+//   length of `to` (u32), then runs of [zero count (varint), literal count (varint), literal bytes]
+//   over `from` XOR `to` (the shorter one padded with zeros)
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let len = from.len().max(to.len());
+    let xor = (0..len).map(|idx| from.get(idx).unwrap_or(&0) ^ to.get(idx).unwrap_or(&0));
+    let diff: Vec<u8> = xor.collect();
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&(to.len() as u32).to_le_bytes());
+
+    let mut idx = 0;
+    while idx < diff.len() {
+        let zeros_start = idx;
+        while idx < diff.len() && diff[idx] == 0 {
+            idx += 1;
+        }
+        let literal_start = idx;
+        while idx < diff.len() && diff[idx] != 0 {
+            idx += 1;
+        }
+        write_varint(&mut out, literal_start - zeros_start);
+        write_varint(&mut out, idx - literal_start);
+        out.extend_from_slice(&diff[literal_start..idx]);
+    }
+    out
+}
+
+fn decode_delta(from: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut len_bytes = [0; 4];
+    len_bytes.copy_from_slice(&delta[..4]);
+    let to_len = u32::from_le_bytes(len_bytes) as usize;
+
+    let mut to = from.to_vec();
+    to.resize(from.len().max(to_len), 0);
+
+    let mut position = 4;
+    let mut idx = 0;
+    while position < delta.len() {
+        idx += read_varint(delta, &mut position);
+        let literals = read_varint(delta, &mut position);
+        for byte in delta[position..position + literals].iter() {
+            to[idx] ^= byte;
+            idx += 1;
+        }
+        position += literals;
+    }
+
+    to.truncate(to_len);
+    to
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*position];
+        *position += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_its_deltas() {
+        let pairs: [(&[u8], &[u8]); 4] = [
+            (&[1, 2, 3, 4], &[1, 2, 3, 4]),
+            (&[0; 300], &[7; 300]),
+            (&[1, 0, 0, 2, 3], &[1, 5, 0, 2]),
+            (&[9], &[9, 8, 7]),
+        ];
+        for (from, to) in pairs.iter() {
+            assert_eq!(decode_delta(from, &encode_delta(from, to)), *to);
+        }
+    }
+
+    #[test]
+    fn pops_the_pushed_states_in_reverse() {
+        let states: Vec<Vec<u8>> = (0..5).map(|frame| vec![frame; 16]).collect();
+        let mut buffer = RewindBuffer::new(1024);
+        for state in states.iter() {
+            buffer.push(state.clone());
+        }
+        assert_eq!(buffer.len(), 4);
+        for state in states[..4].iter().rev() {
+            assert_eq!(buffer.pop().as_ref(), Some(state));
+        }
+        assert_eq!(buffer.pop(), None);
+    }
+
+    #[test]
+    fn drops_the_oldest_deltas_over_budget() {
+        let mut buffer = RewindBuffer::new(64);
+        for frame in 0..20 {
+            buffer.push(vec![frame; 32]);
+        }
+        assert!(buffer.memory_used() <= 64);
+        assert!(buffer.len() < 19);
+        // The most recent frames are still there
+        let mut last = 19;
+        while let Some(state) = buffer.pop() {
+            last -= 1;
+            assert_eq!(state, vec![last; 32]);
+        }
+        assert!(last > 0);
+    }
+}
@@ -0,0 +1,59 @@
+// Random number generator owned by the CPU for CXKK.
+// A small xorshift64* generator: its whole state is a single u64,
+// so it can be captured in save states and restored exactly.
+// Seeding it makes runs reproducible, e.g. for automated tests of games.
+//
+// The `vip_rng` quirk switches to the COSMAC VIP approach instead:
+// the interpreter added a byte read from its own code, through a pointer
+// bumped on every call, to the previous random number. The low page of RAM
+// (the fonts here) stands in for the interpreter code. The pointer and the
+// previous number are kept in the two low bytes of the same u64 state.
+
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck on a zero state
+        Self {
+            state: if seed == 0 {
+                0x9E37_79B9_7F4A_7C15
+            } else {
+                seed
+            },
+        }
+    }
+
+    // Seeded from the system's entropy source
+    pub fn from_entropy() -> Self {
+        Self::new(rand::random())
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        if self.state == 0 {
+            *self = Self::new(0);
+        }
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    // COSMAC VIP random numbers, reading the bytes of `page` (256 bytes)
+    pub fn next_vip_byte(&mut self, page: &[u8]) -> u8 {
+        let pointer = (self.state as u8).wrapping_add(1);
+        let value = ((self.state >> 8) as u8).wrapping_add(page[pointer as usize]);
+        self.state = self.state & !0xFFFF | (value as u64) << 8 | pointer as u64;
+        value
+    }
+
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    // Restore an exact state, unlike `new` which avoids the zero state
+    pub fn set_state(&mut self, state: u64) {
+        self.state = state;
+    }
+}
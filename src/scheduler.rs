@@ -0,0 +1,51 @@
+// Frame scheduler:
+// keeps the emulation loop running at a fixed frame rate (60 Hz for CHIP-8 timers).
+// Each deadline is computed from the previous one rather than from the time the
+// frame finished, so time spent emulating and rendering doesn't accumulate as drift.
+// If the loop falls too far behind (e.g. the window was dragged), it resynchronises
+// instead of running a burst of frames to catch up.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const FRAME_RATE: u32 = 60;
+
+// Maximum number of frames the loop may lag behind before resynchronising
+const MAX_LAG_FRAMES: u32 = 5;
+
+pub struct FrameScheduler {
+    frame_duration: Duration,
+    next_frame: Instant,
+}
+
+impl FrameScheduler {
+    pub fn new(frame_rate: u32) -> Self {
+        Self {
+            frame_duration: Duration::from_secs(1) / frame_rate,
+            next_frame: Instant::now(),
+        }
+    }
+
+    // Sleep until the start of the next frame
+    pub fn wait_for_next_frame(&mut self) {
+        self.next_frame += self.frame_duration;
+
+        let now = Instant::now();
+        if self.next_frame > now {
+            thread::sleep(self.next_frame - now);
+        } else if now - self.next_frame > self.frame_duration * MAX_LAG_FRAMES {
+            self.next_frame = now;
+        }
+    }
+
+    // Restart the schedule from now, e.g. after the emulation was paused
+    pub fn reset(&mut self) {
+        self.next_frame = Instant::now();
+    }
+}
+
+impl Default for FrameScheduler {
+    fn default() -> Self {
+        Self::new(FRAME_RATE)
+    }
+}
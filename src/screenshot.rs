@@ -0,0 +1,52 @@
+// Screenshots of the display:
+// PNG images scaled up in the window's palette, or plain-text ASCII art
+// with one character per pixel: '.' off, '#' plane 1, 'o' plane 2, '@' both.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chiprs::Machine;
+
+use crate::image::Image;
+
+// Default scale of PNG screenshots, the size of the window for the low resolution display
+pub const DEFAULT_SCALE: usize = 10;
+
+const ASCII: [char; 4] = ['.', '#', 'o', '@'];
+
+// Saves as ASCII art if the file ends in `.txt`, as a PNG image otherwise
+pub fn save(machine: &Machine, path: &Path, scale: usize) -> io::Result<()> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("txt") => fs::write(path, ascii(machine)),
+        _ => Image::from_machine(machine, scale.max(1)).save_png(path),
+    }
+}
+
+pub fn ascii(machine: &Machine) -> String {
+    let (width, _) = machine.display_size();
+    let mut text = String::new();
+    for row in machine.framebuffer().chunks(width) {
+        text.extend(row.iter().map(|pixel| ASCII[*pixel as usize & 0x3]));
+        text.push('\n');
+    }
+    text
+}
+
+// First free `<rom>-N.png` path next to the ROM, along with its `.txt` counterpart
+pub fn next_paths(rom_file: &str) -> (PathBuf, PathBuf) {
+    let rom = Path::new(rom_file);
+    let stem = rom
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut number = 1;
+    loop {
+        let png = rom.with_file_name(format!("{}-{}.png", stem, number));
+        let txt = png.with_extension("txt");
+        if !png.exists() && !txt.exists() {
+            return (png, txt);
+        }
+        number += 1;
+    }
+}
@@ -0,0 +1,231 @@
+// Save states:
+// a snapshot captures everything needed to resume the machine at an exact point:
+// CPU registers, stack, timers, RAM, display buffer, keypad and RNG state.
+//
+// Binary format (all integers little-endian):
+//   magic "C8SS", format version (u16), quirks (u8 bit flags)
+//   pc (u32), i (u32), V0-VF (16 bytes)
+//   stack depth (u8) followed by the return addresses (u32 each)
+//   delay timer (u8), sound timer (u8), exited flag (u8)
+//   FX0A key wait flag (u8), key held during the wait (u8, 0xFF if none)   [version 2+]
+//   RPL flags (16 bytes), audio pattern (16 bytes), pitch (u8), RNG state (u64)
+//   held keys (u16 bitmask)
+//   display width (u16), height (u16), plane mask (u8), frame buffer (u32 length + bytes)
+//   RAM (u32 length + bytes)
+
+use crate::cpu::STACK_SIZE;
+use crate::display::{HEIGHT, HIRES_HEIGHT, HIRES_WIDTH, WIDTH};
+use crate::error::{Error, Result};
+use crate::ram::{RAM_SIZE, XO_RAM_SIZE};
+
+const MAGIC: &[u8; 4] = b"C8SS";
+pub const VERSION: u16 = 2;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Snapshot {
+    pub quirks: u8,
+    pub pc: u32,
+    pub i: u32,
+    pub v: [u8; 16],
+    pub stack: Vec<u32>,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub exited: bool,
+    pub waiting_for_key: bool,
+    pub key_down: Option<u8>,
+    pub rpl: [u8; 16],
+    pub audio_pattern: [u8; 16],
+    pub pitch: u8,
+    pub rng_state: u64,
+    pub keys: u16,
+    pub display_width: u16,
+    pub display_height: u16,
+    pub plane_mask: u8,
+    pub frame_buffer: Vec<u8>,
+    pub ram: Vec<u8>,
+}
+
+impl Snapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.ram.len() + self.frame_buffer.len() + 128);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.push(self.quirks);
+        out.extend_from_slice(&self.pc.to_le_bytes());
+        out.extend_from_slice(&self.i.to_le_bytes());
+        out.extend_from_slice(&self.v);
+        out.push(self.stack.len() as u8);
+        for address in self.stack.iter() {
+            out.extend_from_slice(&address.to_le_bytes());
+        }
+        out.push(self.delay_timer);
+        out.push(self.sound_timer);
+        out.push(self.exited as u8);
+        out.push(self.waiting_for_key as u8);
+        out.push(self.key_down.unwrap_or(0xFF));
+        out.extend_from_slice(&self.rpl);
+        out.extend_from_slice(&self.audio_pattern);
+        out.push(self.pitch);
+        out.extend_from_slice(&self.rng_state.to_le_bytes());
+        out.extend_from_slice(&self.keys.to_le_bytes());
+        out.extend_from_slice(&self.display_width.to_le_bytes());
+        out.extend_from_slice(&self.display_height.to_le_bytes());
+        out.push(self.plane_mask);
+        out.extend_from_slice(&(self.frame_buffer.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.frame_buffer);
+        out.extend_from_slice(&(self.ram.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.ram);
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot> {
+        let mut reader = Reader { bytes, position: 0 };
+
+        if reader.take(4)? != MAGIC {
+            return Err(invalid("not a CHIP-8 save state"));
+        }
+        let version = reader.u16()?;
+        if version == 0 || version > VERSION {
+            return Err(Error::InvalidSnapshot(format!(
+                "unsupported save state version {} (expected {} or older)",
+                version, VERSION
+            )));
+        }
+
+        let mut snapshot = Snapshot {
+            quirks: reader.u8()?,
+            pc: reader.u32()?,
+            i: reader.u32()?,
+            ..Default::default()
+        };
+        snapshot.v.copy_from_slice(reader.take(16)?);
+        let depth = reader.u8()?;
+        for _ in 0..depth {
+            snapshot.stack.push(reader.u32()?);
+        }
+        snapshot.delay_timer = reader.u8()?;
+        snapshot.sound_timer = reader.u8()?;
+        snapshot.exited = reader.u8()? != 0;
+        if version >= 2 {
+            snapshot.waiting_for_key = reader.u8()? != 0;
+            snapshot.key_down = Some(reader.u8()?).filter(|key| *key < 16);
+        }
+        snapshot.rpl.copy_from_slice(reader.take(16)?);
+        snapshot.audio_pattern.copy_from_slice(reader.take(16)?);
+        snapshot.pitch = reader.u8()?;
+        snapshot.rng_state = reader.u64()?;
+        snapshot.keys = reader.u16()?;
+        snapshot.display_width = reader.u16()?;
+        snapshot.display_height = reader.u16()?;
+        snapshot.plane_mask = reader.u8()?;
+        let len = reader.u32()? as usize;
+        snapshot.frame_buffer = reader.take(len)?.to_vec();
+        let len = reader.u32()? as usize;
+        snapshot.ram = reader.take(len)?.to_vec();
+
+        snapshot.validate()?;
+        Ok(snapshot)
+    }
+
+    // Check the snapshot fits this machine before any of it gets loaded
+    pub fn validate(&self) -> Result<()> {
+        if self.ram.len() != RAM_SIZE && self.ram.len() != XO_RAM_SIZE {
+            return Err(Error::InvalidSnapshot(format!(
+                "unexpected memory size of {} bytes",
+                self.ram.len()
+            )));
+        }
+        if self.stack.len() > STACK_SIZE {
+            return Err(Error::InvalidSnapshot(format!(
+                "stack depth of {} exceeds {}",
+                self.stack.len(),
+                STACK_SIZE
+            )));
+        }
+        let size = (self.display_width as usize, self.display_height as usize);
+        if size != (WIDTH, HEIGHT) && size != (HIRES_WIDTH, HIRES_HEIGHT) {
+            return Err(Error::InvalidSnapshot(format!(
+                "unexpected display size {}x{}",
+                size.0, size.1
+            )));
+        }
+        if self.frame_buffer.len() != size.0 * size.1 {
+            return Err(invalid("frame buffer doesn't match the display size"));
+        }
+        // PLANE only selects the two XO-CHIP planes
+        if self.plane_mask > 0x3 {
+            return Err(Error::InvalidSnapshot(format!(
+                "unexpected plane mask {:#04x}",
+                self.plane_mask
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidSnapshot(reason.to_string())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + len)
+            .ok_or_else(|| invalid("save state is truncated"))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let mut buf = [0; 2];
+        buf.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::Machine;
+
+    #[test]
+    fn round_trips_through_bytes() {
+        let snapshot = Machine::new(&[0x12, 0x00]).unwrap().snapshot();
+        assert_eq!(
+            Snapshot::from_bytes(&snapshot.to_bytes()).unwrap(),
+            snapshot
+        );
+    }
+
+    #[test]
+    fn rejects_planes_that_dont_exist() {
+        let mut snapshot = Machine::new(&[0x12, 0x00]).unwrap().snapshot();
+        snapshot.plane_mask = 0x4;
+        assert!(matches!(
+            Snapshot::from_bytes(&snapshot.to_bytes()),
+            Err(Error::InvalidSnapshot(_))
+        ));
+    }
+}
@@ -0,0 +1,281 @@
+// Terminal frontend:
+// draws the display with Unicode half blocks (two pixels per character, in colour)
+// or braille patterns (2x4 pixels per character) and reads the keypad in raw mode.
+// Most terminals only report key presses, so a key counts as held for a few frames
+// after its last press or auto-repeat, unless the terminal can report releases.
+
+use std::io::{self, BufWriter, Stdout, Write};
+
+use ratatui::crossterm::cursor::{Hide, MoveTo, Show};
+use ratatui::crossterm::event::{
+    self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use ratatui::crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use ratatui::crossterm::terminal::{
+    self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::crossterm::{execute, queue};
+
+use chiprs::Machine;
+
+use crate::chip8::PALETTE;
+
+// How long a key stays held after a press when releases aren't reported (~130 ms)
+const KEY_HOLD_FRAMES: u32 = 8;
+const HELD: u32 = u32::MAX;
+// Index of the rewind key in the held keys, after the 16 keypad keys
+const REWIND_KEY: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Charset {
+    HalfBlock,
+    Braille,
+}
+
+// Keys read from the terminal since the last frame
+#[derive(Default)]
+pub struct Input {
+    pub keys: u16,
+    pub rewind: bool,
+    pub quit: bool,
+    pub screenshot: bool,
+    // Save state slots used, with true to save and false to load
+    pub state_slots: Vec<(u8, bool)>,
+}
+
+pub struct TerminalScreen {
+    out: BufWriter<Stdout>,
+    charset: Charset,
+    // The terminal reports key releases (kitty keyboard protocol)
+    releases: bool,
+    // Frames left before each key is released, HELD until an actual release
+    held: [u32; 17],
+    size: (usize, usize),
+}
+
+impl TerminalScreen {
+    pub fn new(charset: Charset) -> io::Result<Self> {
+        // Built first so that Drop restores the terminal if any of the setup fails
+        let mut screen = Self {
+            out: BufWriter::new(io::stdout()),
+            charset,
+            releases: false,
+            held: [0; 17],
+            size: (0, 0),
+        };
+        execute!(
+            screen.out,
+            EnterAlternateScreen,
+            Hide,
+            Clear(ClearType::All)
+        )?;
+        terminal::enable_raw_mode()?;
+        if terminal::supports_keyboard_enhancement().unwrap_or(false) {
+            execute!(
+                screen.out,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+            screen.releases = true;
+        }
+        Ok(screen)
+    }
+
+    pub fn poll_input(&mut self) -> io::Result<Input> {
+        let mut input = Input::default();
+        while event::poll(std::time::Duration::ZERO)? {
+            let key = match event::read()? {
+                Event::Key(key) => key,
+                Event::Resize(_, _) => {
+                    // Force a full redraw at the next frame
+                    self.size = (0, 0);
+                    continue;
+                }
+                _ => continue,
+            };
+            let pressed = key.kind != KeyEventKind::Release;
+            let held = match key.code {
+                KeyCode::Esc => {
+                    input.quit = true;
+                    None
+                }
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    input.quit = true;
+                    None
+                }
+                KeyCode::F(slot @ 1..=9) if key.kind == KeyEventKind::Press => {
+                    input
+                        .state_slots
+                        .push((slot, key.modifiers.contains(KeyModifiers::SHIFT)));
+                    None
+                }
+                KeyCode::Char('p') | KeyCode::Char('P') if key.kind == KeyEventKind::Press => {
+                    input.screenshot = true;
+                    None
+                }
+                KeyCode::Backspace => Some(REWIND_KEY),
+                KeyCode::Char(c) => decode_char(c).map(|key| key as usize),
+                _ => None,
+            };
+            if let Some(idx) = held {
+                self.held[idx] = match (pressed, self.releases) {
+                    (false, _) => 0,
+                    (true, true) => HELD,
+                    (true, false) => KEY_HOLD_FRAMES,
+                };
+            }
+        }
+
+        for (idx, frames) in self.held.iter_mut().enumerate() {
+            if *frames == 0 {
+                continue;
+            }
+            if idx == REWIND_KEY {
+                input.rewind = true;
+            } else {
+                input.keys |= 1 << idx;
+            }
+            if *frames != HELD {
+                *frames -= 1;
+            }
+        }
+        Ok(input)
+    }
+
+    pub fn draw(&mut self, machine: &Machine) -> io::Result<()> {
+        let size = machine.display_size();
+        if size != self.size {
+            queue!(self.out, ResetColor, Clear(ClearType::All))?;
+            self.size = size;
+        }
+        match self.charset {
+            Charset::HalfBlock => self.draw_half_blocks(machine)?,
+            Charset::Braille => self.draw_braille(machine)?,
+        }
+        queue!(self.out, ResetColor)?;
+        self.out.flush()
+    }
+
+    // Messages go on the line below the display, printing them would scroll the alternate screen
+    pub fn show_message(&mut self, message: &str) -> io::Result<()> {
+        let rows = match self.charset {
+            Charset::HalfBlock => self.size.1 / 2,
+            Charset::Braille => self.size.1 / 4,
+        };
+        queue!(
+            self.out,
+            MoveTo(0, rows as u16),
+            ResetColor,
+            Clear(ClearType::CurrentLine),
+            Print(message)
+        )?;
+        self.out.flush()
+    }
+
+    pub fn bell(&mut self) -> io::Result<()> {
+        queue!(self.out, Print('\x07'))?;
+        self.out.flush()
+    }
+
+    // Upper half block: the top pixel in the foreground colour, the bottom one in the background
+    fn draw_half_blocks(&mut self, machine: &Machine) -> io::Result<()> {
+        let (width, height) = machine.display_size();
+        let pixels = machine.framebuffer();
+        let mut colours = (None, None);
+        for row in 0..height / 2 {
+            queue!(self.out, MoveTo(0, row as u16))?;
+            for x in 0..width {
+                let top = colour(pixels[row * 2 * width + x]);
+                let bottom = colour(pixels[(row * 2 + 1) * width + x]);
+                if colours.0 != Some(top) {
+                    queue!(self.out, SetForegroundColor(top))?;
+                }
+                if colours.1 != Some(bottom) {
+                    queue!(self.out, SetBackgroundColor(bottom))?;
+                }
+                colours = (Some(top), Some(bottom));
+                queue!(self.out, Print('▀'))?;
+            }
+        }
+        Ok(())
+    }
+
+    // A braille character holds 2x4 pixels in a single colour, the brightest one of the cell
+    fn draw_braille(&mut self, machine: &Machine) -> io::Result<()> {
+        // Dot bits of the braille patterns, by row then column
+        const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+        let (width, height) = machine.display_size();
+        let pixels = machine.framebuffer();
+        let mut current = None;
+        queue!(self.out, SetBackgroundColor(colour(0)))?;
+        for row in 0..height / 4 {
+            queue!(self.out, MoveTo(0, row as u16))?;
+            for column in 0..width / 2 {
+                let mut dots = 0;
+                let mut brightest = 0;
+                for (dy, row_dots) in DOTS.iter().enumerate() {
+                    for (dx, dot) in row_dots.iter().enumerate() {
+                        let pixel = pixels[(row * 4 + dy) * width + column * 2 + dx];
+                        if pixel != 0 {
+                            dots |= dot;
+                            brightest = brightest.max(pixel);
+                        }
+                    }
+                }
+                let fg = colour(brightest);
+                if current != Some(fg) {
+                    queue!(self.out, SetForegroundColor(fg))?;
+                    current = Some(fg);
+                }
+                let c = std::char::from_u32(0x2800 + dots).unwrap_or(' ');
+                queue!(self.out, Print(c))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TerminalScreen {
+    fn drop(&mut self) {
+        if self.releases {
+            let _ = execute!(self.out, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(self.out, ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn colour(pixel: u8) -> Color {
+    let rgb = PALETTE[pixel as usize & 0x3];
+    Color::Rgb {
+        r: (rgb >> 16) as u8,
+        g: (rgb >> 8) as u8,
+        b: rgb as u8,
+    }
+}
+
+// Same layout as the window: 1234 / QWER / ASDF / ZXCV
+fn decode_char(c: char) -> Option<u8> {
+    match c.to_ascii_lowercase() {
+        '1' => Some(0x1),
+        '2' => Some(0x2),
+        '3' => Some(0x3),
+        '4' => Some(0xC),
+
+        'q' => Some(0x4),
+        'w' => Some(0x5),
+        'e' => Some(0x6),
+        'r' => Some(0xD),
+
+        'a' => Some(0x7),
+        's' => Some(0x8),
+        'd' => Some(0x9),
+        'f' => Some(0xE),
+
+        'z' => Some(0xA),
+        'x' => Some(0x0),
+        'c' => Some(0xB),
+        'v' => Some(0xF),
+        _ => None,
+    }
+}
@@ -0,0 +1,284 @@
+// Headless test runner (`chip8rs test <MANIFEST>`):
+// runs ROMs for a number of frames with scripted input, then checks the display
+// against the expected SHA-1 of the frame buffer and/or an expected PNG image.
+//
+// The manifest holds one section per test, paths are relative to the manifest:
+//   [pong]
+//   rom = roms/pong.ch8
+//   frames = 300
+//   input = 30:1 60:- 90:4    # from frame 30 hold key 1, from 60 nothing, from 90 key 4
+//   hash = 36c5e25a55ca6162a0ac34dd397482b5c7083352
+// Optional settings: `quirks` (preset name, legacy by default), `ipf` (instructions
+// per frame), `seed` (of the RNG, 0 by default) and `png` (expected image).
+// On failure the actual display and a diff (differences in red) are written as PNG files.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chiprs::machine::DEFAULT_CYCLES_PER_FRAME;
+use chiprs::{Error, Machine, Quirks, Result};
+
+use crate::image::Image;
+
+#[derive(Debug, Default)]
+struct Test {
+    name: String,
+    rom: PathBuf,
+    quirks: Quirks,
+    cycles_per_frame: usize,
+    seed: u64,
+    frames: u64,
+    // Frame from which the keys are held, sorted by frame
+    input: Vec<(u64, u16)>,
+    hash: Option<String>,
+    png: Option<PathBuf>,
+}
+
+// Runs every test of the manifest, returns true if they all passed
+pub fn run(manifest: &Path, output: Option<&Path>) -> Result<bool> {
+    let tests = parse_manifest(manifest)?;
+    let output = output
+        .or_else(|| manifest.parent())
+        .unwrap_or_else(|| Path::new("."));
+    let mut failed = 0;
+    for test in tests.iter() {
+        match test.run(output) {
+            Ok(()) => println!("{} ... ok", test.name),
+            Err(reason) => {
+                println!("{} ... FAILED: {}", test.name, reason);
+                failed += 1;
+            }
+        }
+    }
+    println!("{} passed, {} failed", tests.len() - failed, failed);
+    Ok(failed == 0)
+}
+
+impl Test {
+    fn run(&self, output: &Path) -> std::result::Result<(), String> {
+        let rom = fs::read(&self.rom).map_err(|e| format!("{}: {}", self.rom.display(), e))?;
+        let mut machine = Machine::with_quirks(&rom, self.quirks).map_err(|e| e.to_string())?;
+        machine.set_cycles_per_frame(self.cycles_per_frame);
+        machine.set_seed(self.seed);
+
+        let mut input = self.input.iter().peekable();
+        for frame in 0..self.frames {
+            while let Some((_, keys)) = input.next_if(|(start, _)| *start <= frame) {
+                machine.set_keys(*keys);
+            }
+            machine
+                .run_frame()
+                .map_err(|e| format!("frame {}: {}", frame, e))?;
+            if machine.has_exited() {
+                break;
+            }
+        }
+
+        let expected = match &self.png {
+            Some(path) => {
+                Some(Image::load_png(path).map_err(|e| format!("{}: {}", path.display(), e))?)
+            }
+            None => None,
+        };
+        // The display is scaled up to the expected image by a whole factor, or not at all
+        let (width, _) = machine.display_size();
+        let scale = expected
+            .as_ref()
+            .map_or(1, |expected| (expected.width / width).max(1));
+        let actual = Image::from_machine(&machine, scale);
+
+        let mut failures = Vec::new();
+        let hash = sha1_smol::Sha1::from(machine.framebuffer())
+            .digest()
+            .to_string();
+        if let Some(expected) = &self.hash {
+            if !expected.eq_ignore_ascii_case(&hash) {
+                failures.push(format!("frame buffer hash {}, expected {}", hash, expected));
+            }
+        }
+        if let Some(expected) = &expected {
+            if let Some(reason) = self.compare(&actual, expected, output) {
+                failures.push(reason);
+            }
+        }
+        if failures.is_empty() {
+            return Ok(());
+        }
+
+        // Whatever failed, the display is saved to be looked at
+        let actual_path = output.join(format!("{}.actual.png", self.name));
+        failures.push(match actual.save_png(&actual_path) {
+            Ok(()) => format!("display saved to {}", actual_path.display()),
+            Err(e) => format!("saving the display failed: {}", e),
+        });
+        Err(failures.join(", "))
+    }
+
+    // Compare the display with an image, the diff is written if they differ
+    fn compare(&self, actual: &Image, expected: &Image, output: &Path) -> Option<String> {
+        if actual.width != expected.width || actual.height != expected.height {
+            return Some(format!(
+                "display is {}x{}, expected image is {}x{}",
+                actual.width, actual.height, expected.width, expected.height
+            ));
+        }
+        let different = actual
+            .pixels
+            .iter()
+            .zip(&expected.pixels)
+            .filter(|(actual, expected)| actual != expected)
+            .count();
+        if different == 0 {
+            return None;
+        }
+
+        // Differences in red over a dimmed copy of the expected image
+        let diff = Image {
+            pixels: actual
+                .pixels
+                .iter()
+                .zip(&expected.pixels)
+                .map(|(actual, expected)| {
+                    if actual != expected {
+                        0xff0000
+                    } else {
+                        expected >> 2 & 0x3f3f3f
+                    }
+                })
+                .collect(),
+            ..actual.clone()
+        };
+        let diff_path = output.join(format!("{}.diff.png", self.name));
+        if let Err(e) = diff.save_png(&diff_path) {
+            return Some(format!(
+                "{} pixels differ from the image, saving the diff failed: {}",
+                different, e
+            ));
+        }
+        Some(format!(
+            "{} pixels differ from the image, see {}",
+            different,
+            diff_path.display()
+        ))
+    }
+}
+
+fn parse_manifest(path: &Path) -> Result<Vec<Test>> {
+    let text = fs::read_to_string(path)?;
+    let base = path.parent().unwrap_or_else(|| Path::new("."));
+    let error = |reason: String| {
+        Error::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), reason),
+        ))
+    };
+    let invalid = |line: usize, reason: String| error(format!("line {}: {}", line + 1, reason));
+
+    let mut tests: Vec<Test> = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            tests.push(Test {
+                name: name.trim().to_string(),
+                cycles_per_frame: DEFAULT_CYCLES_PER_FRAME,
+                ..Default::default()
+            });
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .ok_or_else(|| invalid(idx, format!("expected `key = value`, found '{}'", line)))?;
+        let test = tests
+            .last_mut()
+            .ok_or_else(|| invalid(idx, String::from("setting outside of a [test] section")))?;
+        let number = |value: &str| {
+            value
+                .parse::<u64>()
+                .map_err(|_| invalid(idx, format!("invalid number '{}'", value)))
+        };
+        match key {
+            "rom" => test.rom = base.join(value),
+            "quirks" => {
+                test.quirks = Quirks::from_name(value)
+                    .ok_or_else(|| invalid(idx, format!("unknown quirks preset '{}'", value)))?
+            }
+            "ipf" => test.cycles_per_frame = number(value)?.max(1) as usize,
+            "seed" => test.seed = number(value)?,
+            "frames" => test.frames = number(value)?,
+            "input" => test.input = parse_input(value).map_err(|e| invalid(idx, e))?,
+            "hash" => test.hash = Some(value.to_string()),
+            "png" => test.png = Some(base.join(value)),
+            _ => return Err(invalid(idx, format!("unknown setting '{}'", key))),
+        }
+    }
+
+    for test in tests.iter() {
+        if test.rom.as_os_str().is_empty() || test.frames == 0 {
+            return Err(error(format!(
+                "test '{}' needs a rom and a number of frames",
+                test.name
+            )));
+        }
+        if test.hash.is_none() && test.png.is_none() {
+            return Err(error(format!(
+                "test '{}' needs an expected hash or png",
+                test.name
+            )));
+        }
+    }
+    Ok(tests)
+}
+
+// `FRAME:KEYS` events separated by spaces, keys as hex digits or `-` for none
+fn parse_input(script: &str) -> std::result::Result<Vec<(u64, u16)>, String> {
+    let mut events = Vec::new();
+    for event in script.split_whitespace() {
+        let (frame, keys) = event
+            .split_once(':')
+            .ok_or_else(|| format!("expected FRAME:KEYS, found '{}'", event))?;
+        let frame = frame
+            .parse::<u64>()
+            .map_err(|_| format!("invalid frame '{}'", frame))?;
+        let mut mask = 0;
+        if keys != "-" {
+            for key in keys.chars() {
+                let key = key
+                    .to_digit(16)
+                    .ok_or_else(|| format!("invalid key '{}'", key))?;
+                mask |= 1 << key;
+            }
+        }
+        events.push((frame, mask));
+    }
+    events.sort_by_key(|(frame, _)| *frame);
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_the_display_when_the_hash_differs() {
+        let dir = std::env::temp_dir().join(format!("chiprs-test-runner-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // JP 0x200
+        fs::write(dir.join("loop.ch8"), [0x12, 0x00]).unwrap();
+        let manifest = dir.join("tests.txt");
+        fs::write(
+            &manifest,
+            "[loop]\nrom = loop.ch8\nframes = 2\nhash = 0000000000000000000000000000000000000000\n",
+        )
+        .unwrap();
+
+        let passed = run(&manifest, None).unwrap();
+        let saved = dir.join("loop.actual.png").exists();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(!passed);
+        assert!(saved);
+    }
+}
@@ -0,0 +1,272 @@
+// Instruction trace:
+// one record per executed instruction with the state before executing it,
+// for diffing the behaviour of interpreters. Records are written as text,
+// one line each, or in a compact binary form for long runs:
+//   header: "C8TR" and a version byte,
+//   then 33 bytes per record, little endian: cycle (u64), PC (u16), opcode (u16),
+//   V0-VF, I (u16), SP, DT, ST.
+// Filters restrict the trace to address ranges and to opcode classes (first nibble),
+// the cycle count still counts every instruction.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+
+use crate::cpu::Cpu;
+use crate::disassembler::Disassembler;
+use crate::error::Result;
+
+const MAGIC: &[u8; 4] = b"C8TR";
+const VERSION: u8 = 1;
+const RECORD_SIZE: usize = 33;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    Text,
+    Binary,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    // Inclusive address ranges of the PC, empty traces everywhere
+    ranges: Vec<(usize, usize)>,
+    // Bit N set traces the opcodes starting with nibble N, 0 traces all of them
+    classes: u16,
+}
+
+impl TraceFilter {
+    // Address range given as `START-END` or a single address, in hexadecimal
+    pub fn add_range(&mut self, range: &str) -> std::result::Result<(), String> {
+        let parse = |address: &str| {
+            let address = address.trim();
+            let digits = address
+                .strip_prefix("0x")
+                .or_else(|| address.strip_prefix("0X"))
+                .unwrap_or(address);
+            usize::from_str_radix(digits, 16).map_err(|_| format!("invalid address '{}'", address))
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (parse(start)?, parse(end)?),
+            None => {
+                let address = parse(range)?;
+                (address, address)
+            }
+        };
+        if start > end {
+            return Err(format!("empty address range '{}'", range));
+        }
+        self.ranges.push((start, end));
+        Ok(())
+    }
+
+    // Opcode class given as its first nibble, e.g. `D` for sprites or `F` for FX instructions
+    pub fn add_class(&mut self, class: &str) -> std::result::Result<(), String> {
+        match u8::from_str_radix(class.trim(), 16) {
+            Ok(nibble) if nibble < 16 => {
+                self.classes |= 1 << nibble;
+                Ok(())
+            }
+            _ => Err(format!("invalid opcode class '{}', expected 0-F", class)),
+        }
+    }
+
+    pub fn matches(&self, pc: usize, opcode: u16) -> bool {
+        let in_range = self.ranges.is_empty()
+            || self
+                .ranges
+                .iter()
+                .any(|(start, end)| (*start..=*end).contains(&pc));
+        let in_class = self.classes == 0 || self.classes & 1 << (opcode >> 12) != 0;
+        in_range && in_class
+    }
+}
+
+// State of the machine before executing an instruction
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceRecord {
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub sp: u8,
+    pub dt: u8,
+    pub st: u8,
+}
+
+impl TraceRecord {
+    fn new(cycle: u64, cpu: &Cpu, opcode: u16) -> Self {
+        Self {
+            cycle,
+            pc: cpu.pc as u16,
+            opcode,
+            v: cpu.v,
+            i: cpu.i as u16,
+            sp: cpu.stack().len() as u8,
+            dt: cpu.delay_timer(),
+            st: cpu.sound_timer(),
+        }
+    }
+
+    fn to_bytes(&self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0; RECORD_SIZE];
+        bytes[0..8].copy_from_slice(&self.cycle.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.pc.to_le_bytes());
+        bytes[10..12].copy_from_slice(&self.opcode.to_le_bytes());
+        bytes[12..28].copy_from_slice(&self.v);
+        bytes[28..30].copy_from_slice(&self.i.to_le_bytes());
+        bytes[30] = self.sp;
+        bytes[31] = self.dt;
+        bytes[32] = self.st;
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; RECORD_SIZE]) -> Self {
+        let u16_at = |idx: usize| u16::from_le_bytes([bytes[idx], bytes[idx + 1]]);
+        let mut cycle = [0; 8];
+        cycle.copy_from_slice(&bytes[0..8]);
+        let mut v = [0; 16];
+        v.copy_from_slice(&bytes[12..28]);
+        Self {
+            cycle: u64::from_le_bytes(cycle),
+            pc: u16_at(8),
+            opcode: u16_at(10),
+            v,
+            i: u16_at(28),
+            sp: bytes[30],
+            dt: bytes[31],
+            st: bytes[32],
+        }
+    }
+}
+
+// The text form of the trace, one line per instruction
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>10} {:04X} {:04X} {:<20}",
+            self.cycle,
+            self.pc,
+            self.opcode,
+            Disassembler::decode_op(self.opcode)
+        )?;
+        for (idx, value) in self.v.iter().enumerate() {
+            write!(f, " V{:X}={:02X}", idx, value)?;
+        }
+        write!(
+            f,
+            " I={:04X} SP={:X} DT={:02X} ST={:02X}",
+            self.i, self.sp, self.dt, self.st
+        )
+    }
+}
+
+pub struct Tracer {
+    out: Box<dyn Write>,
+    format: TraceFormat,
+    filter: TraceFilter,
+    cycle: u64,
+}
+
+impl Tracer {
+    pub fn new(mut out: Box<dyn Write>, format: TraceFormat, filter: TraceFilter) -> Result<Self> {
+        if format == TraceFormat::Binary {
+            out.write_all(MAGIC)?;
+            out.write_all(&[VERSION])?;
+        }
+        Ok(Self {
+            out,
+            format,
+            filter,
+            cycle: 0,
+        })
+    }
+
+    pub fn create(path: &str, format: TraceFormat, filter: TraceFilter) -> Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        Self::new(Box::new(file), format, filter)
+    }
+
+    // Called by the CPU with each instruction it is about to execute
+    pub(crate) fn record(&mut self, cpu: &Cpu, opcode: u16) -> Result<()> {
+        let cycle = self.cycle;
+        self.cycle += 1;
+        if !self.filter.matches(cpu.pc, opcode) {
+            return Ok(());
+        }
+        let record = TraceRecord::new(cycle, cpu, opcode);
+        match self.format {
+            TraceFormat::Text => writeln!(self.out, "{}", record)?,
+            TraceFormat::Binary => self.out.write_all(&record.to_bytes())?,
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+// Read back a binary trace, e.g. to convert it to text with the `Display` of each record
+pub fn read_binary<R: Read>(mut input: R) -> io::Result<Vec<TraceRecord>> {
+    let mut header = [0; 5];
+    input.read_exact(&mut header)?;
+    if &header[..4] != MAGIC || header[4] != VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a binary trace",
+        ));
+    }
+    let mut records = Vec::new();
+    let mut bytes = [0; RECORD_SIZE];
+    loop {
+        match input.read_exact(&mut bytes) {
+            Ok(()) => records.push(TraceRecord::from_bytes(&bytes)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(records),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::Machine;
+
+    #[test]
+    fn reads_back_a_binary_trace() {
+        let path = std::env::temp_dir().join(format!("chiprs-trace-{}.bin", std::process::id()));
+        let machine = Machine::new(&[0x60, 0x05, 0x12, 0x00]).unwrap();
+        let mut filter = TraceFilter::default();
+        filter.add_class("6").unwrap();
+        let mut tracer =
+            Tracer::create(path.to_str().unwrap(), TraceFormat::Binary, filter).unwrap();
+        for opcode in [0x6005, 0x1200, 0x6105] {
+            tracer.record(machine.cpu(), opcode).unwrap();
+        }
+        drop(tracer);
+
+        let records = read_binary(File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let expected: Vec<_> = [(0, 0x6005), (2, 0x6105)]
+            .iter()
+            .map(|&(cycle, opcode)| TraceRecord::new(cycle, machine.cpu(), opcode))
+            .collect();
+        assert_eq!(records, expected);
+        assert_eq!(records[1].pc, 0x200);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let error = read_binary(&b"C8MV\x01"[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
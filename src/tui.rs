@@ -0,0 +1,340 @@
+// Terminal UI debugger:
+// shown in the terminal alongside the game window, redrawn every frame.
+// Panes: disassembly around the PC, registers/stack/timers, a text rendering
+// of the display and a hex view of memory with I and the PC highlighted.
+// Keyboard driven, the keys are listed in the status line.
+
+use std::io::Stdout;
+use std::time::Duration;
+
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::{Frame, Terminal};
+
+use chiprs::debugger::{self, Debugger};
+use chiprs::{Machine, Result};
+
+const DISASSEMBLY_WIDTH: u16 = 40;
+const REGISTERS_WIDTH: u16 = 26;
+// Bytes per row of the memory pane, 16 when it is wide enough
+const MEMORY_ROW_BYTES: usize = 8;
+const WIDE_MEMORY_ROW_BYTES: usize = 16;
+
+pub struct Tui {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+    debugger: Debugger,
+    // Selected instruction in the disassembly, None follows the PC
+    cursor: Option<usize>,
+    // First address shown in the memory pane, None follows I
+    memory_start: Option<usize>,
+    // Bytes in a page of the memory pane, as last drawn
+    memory_page: usize,
+    message: String,
+}
+
+impl Tui {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            terminal: ratatui::try_init()?,
+            debugger: Debugger::new(),
+            cursor: None,
+            memory_start: None,
+            memory_page: MEMORY_ROW_BYTES * 8,
+            message: String::from("Paused"),
+        })
+    }
+
+    // Handle the pending key presses, run a frame under the debugger and redraw
+    // Returns false once the user quits
+    pub fn run_frame(&mut self, machine: &mut Machine) -> Result<bool> {
+        while event::poll(Duration::ZERO)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                let ctrl_c =
+                    key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if key.code == KeyCode::Char('q') || ctrl_c {
+                    return Ok(false);
+                }
+                self.handle_key(key.code, machine);
+            }
+        }
+
+        let was_running = !self.debugger.is_paused();
+        if let Err(e) = self.debugger.run_frame(machine) {
+            self.message = format!("Error: {}", e);
+        } else if was_running && self.debugger.is_paused() {
+            self.message = match self.debugger.take_stop() {
+                Some(stop) => stop.to_string(),
+                None => String::from("Paused"),
+            };
+        }
+
+        let view = View {
+            debugger: &self.debugger,
+            cursor: self.cursor,
+            memory_start: self.memory_start,
+            message: &self.message,
+        };
+        let mut memory_page = self.memory_page;
+        self.terminal
+            .draw(|frame| memory_page = view.draw(frame, machine))?;
+        self.memory_page = memory_page;
+        Ok(true)
+    }
+
+    fn handle_key(&mut self, code: KeyCode, machine: &Machine) {
+        let pc = machine.cpu().pc;
+        let cursor = self.cursor.unwrap_or(pc);
+        let memory_size = machine.cpu().ram.size();
+        match code {
+            KeyCode::Char('c') | KeyCode::F(5) => {
+                self.debugger.resume();
+                self.message = String::from("Running");
+            }
+            KeyCode::Char('p') => self.debugger.pause(),
+            KeyCode::Char('s') | KeyCode::F(11) => self.debugger.step(),
+            KeyCode::Char('n') | KeyCode::F(10) => self.debugger.step_over(machine),
+            KeyCode::Char('r') => {
+                self.debugger.run_to(cursor);
+                self.message = format!("Running to {:#05X}", cursor);
+            }
+            KeyCode::Char('b') => {
+                if self.debugger.add_breakpoint(cursor) {
+                    self.message = format!("Breakpoint set at {:#05X}", cursor);
+                } else {
+                    self.debugger.remove_breakpoint(cursor);
+                    self.message = format!("Breakpoint at {:#05X} removed", cursor);
+                }
+            }
+            KeyCode::Up => self.cursor = Some(cursor.saturating_sub(2)),
+            KeyCode::Down => self.cursor = Some((cursor + 2).min(memory_size - 2)),
+            KeyCode::Char('.') => self.cursor = None,
+            KeyCode::PageUp | KeyCode::PageDown => {
+                let start = self.memory_start.unwrap_or(machine.cpu().i);
+                let page = self.memory_page;
+                self.memory_start = Some(if code == KeyCode::PageUp {
+                    start.saturating_sub(page)
+                } else {
+                    (start + page).min(memory_size - 1)
+                });
+            }
+            KeyCode::Char('i') => self.memory_start = None,
+            _ => {}
+        }
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+struct View<'a> {
+    debugger: &'a Debugger,
+    cursor: Option<usize>,
+    memory_start: Option<usize>,
+    message: &'a str,
+}
+
+impl<'a> View<'a> {
+    // Returns the number of bytes in a page of the memory pane
+    fn draw(&self, frame: &mut Frame, machine: &Machine) -> usize {
+        let (_, display_height) = machine.display_size();
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(display_height as u16 / 2 + 2),
+                Constraint::Min(0),
+                Constraint::Length(1),
+            ])
+            .split(frame.area());
+        let top = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([
+                Constraint::Length(DISASSEMBLY_WIDTH),
+                Constraint::Length(REGISTERS_WIDTH),
+                Constraint::Min(0),
+            ])
+            .split(rows[0]);
+
+        self.draw_disassembly(frame, top[0], machine);
+        self.draw_registers(frame, top[1], machine);
+        self.draw_display(frame, top[2], machine);
+        let memory_page = self.draw_memory(frame, rows[1], machine);
+
+        let state = if self.debugger.is_paused() {
+            "PAUSED"
+        } else {
+            "RUNNING"
+        };
+        let status = format!(
+            " {} | {} | c:continue p:pause s:step n:next r:run to b:breakpoint \
+             up/down:select .:PC pgup/pgdn:memory i:follow I q:quit",
+            state, self.message
+        );
+        frame.render_widget(
+            Paragraph::new(status).style(Style::default().add_modifier(Modifier::REVERSED)),
+            rows[2],
+        );
+        memory_page
+    }
+
+    fn draw_disassembly(&self, frame: &mut Frame, area: Rect, machine: &Machine) {
+        let pc = machine.cpu().pc;
+        let selected = self.cursor.unwrap_or(pc);
+        let visible = area.height.saturating_sub(2) as usize;
+        // Instructions are 2 bytes long, except for XO-CHIP F000 NNNN
+        let mut address = selected.saturating_sub(visible / 3 * 2);
+        let mut lines = Vec::new();
+        for _ in 0..visible {
+            let (instruction, size) = debugger::disassemble(machine, address);
+            let breakpoint = self.debugger.breakpoints().any(|bp| *bp == address);
+            let marker = match (address == pc, breakpoint) {
+                (true, true) => ">*",
+                (true, false) => "> ",
+                (false, true) => " *",
+                (false, false) => "  ",
+            };
+            let mut style = Style::default();
+            if breakpoint {
+                style = style.fg(Color::Red);
+            }
+            if address == pc {
+                style = style.fg(Color::Yellow).add_modifier(Modifier::BOLD);
+            }
+            if self.cursor == Some(address) {
+                style = style.add_modifier(Modifier::REVERSED);
+            }
+            lines.push(Line::styled(
+                format!("{}{:#05X} {}", marker, address, instruction),
+                style,
+            ));
+            address += size;
+        }
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" Disassembly ")),
+            area,
+        );
+    }
+
+    fn draw_registers(&self, frame: &mut Frame, area: Rect, machine: &Machine) {
+        let cpu = machine.cpu();
+        let mut lines = Vec::new();
+        for idx in 0..8 {
+            lines.push(Line::from(format!(
+                "V{:X}={:02X}    V{:X}={:02X}",
+                idx,
+                cpu.v[idx],
+                idx + 8,
+                cpu.v[idx + 8]
+            )));
+        }
+        lines.push(Line::from(format!("I ={:#06X} PC={:#05X}", cpu.i, cpu.pc)));
+        lines.push(Line::from(format!(
+            "DT={:02X}    ST={:02X}",
+            cpu.delay_timer(),
+            cpu.sound_timer()
+        )));
+        if cpu.is_waiting_for_key() {
+            lines.push(Line::styled(
+                "Waiting for key",
+                Style::default().fg(Color::Cyan),
+            ));
+        }
+        lines.push(Line::from(format!("Stack ({})", cpu.stack().len())));
+        for address in cpu.stack().iter().rev() {
+            lines.push(Line::from(format!("  {:#05X}", address)));
+        }
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" Registers ")),
+            area,
+        );
+    }
+
+    // Two pixel rows per character with half blocks
+    fn draw_display(&self, frame: &mut Frame, area: Rect, machine: &Machine) {
+        let (width, height) = machine.display_size();
+        let pixels = machine.framebuffer();
+        let lines: Vec<Line> = (0..height)
+            .step_by(2)
+            .map(|y| {
+                let row: String = (0..width)
+                    .map(|x| {
+                        let top = pixels[y * width + x] != 0;
+                        let bottom = pixels[(y + 1) * width + x] != 0;
+                        match (top, bottom) {
+                            (true, true) => '█',
+                            (true, false) => '▀',
+                            (false, true) => '▄',
+                            (false, false) => ' ',
+                        }
+                    })
+                    .collect();
+                Line::from(row)
+            })
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" Display ")),
+            area,
+        );
+    }
+
+    fn draw_memory(&self, frame: &mut Frame, area: Rect, machine: &Machine) -> usize {
+        let cpu = machine.cpu();
+        let memory = &cpu.ram.memory;
+        // Address, then 3 characters per byte, within the borders
+        let row_bytes = if area.width as usize >= 9 + WIDE_MEMORY_ROW_BYTES * 3 + 2 {
+            WIDE_MEMORY_ROW_BYTES
+        } else {
+            MEMORY_ROW_BYTES
+        };
+        let start = self
+            .memory_start
+            .unwrap_or_else(|| cpu.i.saturating_sub(row_bytes * 2));
+        let start = start - start % row_bytes;
+        let visible = area.height.saturating_sub(2) as usize;
+
+        let i_style = Style::default().fg(Color::Black).bg(Color::Yellow);
+        let pc_style = Style::default().fg(Color::Black).bg(Color::Cyan);
+        let mut lines = Vec::new();
+        for row in 0..visible {
+            let row_start = start + row * row_bytes;
+            if row_start >= memory.len() {
+                break;
+            }
+            let mut spans = vec![Span::raw(format!("{:#06X}:", row_start))];
+            let row_end = (row_start + row_bytes).min(memory.len());
+            for (address, byte) in (row_start..).zip(&memory[row_start..row_end]) {
+                let style = if address == cpu.i {
+                    i_style
+                } else if address == cpu.pc || address == cpu.pc + 1 {
+                    pc_style
+                } else {
+                    Style::default()
+                };
+                spans.push(Span::raw(" "));
+                spans.push(Span::styled(format!("{:02X}", byte), style));
+            }
+            lines.push(Line::from(spans));
+        }
+        let title = Line::from(vec![
+            Span::raw(" Memory "),
+            Span::styled(" I ", i_style),
+            Span::raw(" "),
+            Span::styled(" PC ", pc_style),
+            Span::raw(" "),
+        ]);
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(title)),
+            area,
+        );
+        row_bytes * visible.max(1)
+    }
+}